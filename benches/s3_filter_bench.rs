@@ -1,7 +1,10 @@
 use std::time::Duration;
 
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use linz_s3::linz_s3_filter::{dataset, linz_bucket::LinzBucket};
+use linz_s3::linz_s3_filter::{
+    dataset,
+    linz_bucket::{LinzBucket, TileQuery},
+};
 use tokio::{runtime::Runtime, time::sleep};
 
 async fn empty_collections(multiplier: &usize) {
@@ -27,10 +30,12 @@ async fn get_tiles_from_lat_lon(
 
     loop {
         match LinzBucket::initialise_catalog(dataset, Some(*multiplier)).await {
-            Ok(mut linz_bucket) => {
-                let _tiles = linz_bucket
-                    .get_tiles(Some(lat), Some(lon), None, None)
-                    .await;
+            Ok(linz_bucket) => {
+                let query = TileQuery {
+                    bounds: Some((lon, lat, lon, lat)),
+                    ..Default::default()
+                };
+                let _tiles = linz_bucket.get_tiles(&query).await;
                 break;
             }
             Err(e) => {
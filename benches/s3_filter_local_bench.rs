@@ -2,7 +2,9 @@ use criterion::{criterion_group, criterion_main, Criterion};
 use stac_io::parse_href;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
 
+use linz_s3::linz_s3_filter::fetch::{FetchFailures, FetchPolicy};
 use linz_s3::linz_s3_filter::reporter::Reporter;
 use linz_s3::linz_s3_filter::utils;
 use linz_s3::linz_s3_filter::{dataset, linz_bucket::CollectionTaskContext};
@@ -29,11 +31,14 @@ fn bench_process_collection(c: &mut Criterion) {
                     store,
                     reporter,
                     semaphore,
+                    workers: 100,
+                    index_dir: None,
+                    items: None,
+                    cancel: CancellationToken::new(),
+                    fetch_policy: FetchPolicy::default(),
+                    failures: FetchFailures::default(),
                 },
-                Some(172.93),
-                Some(1.35),
-                None,
-                None,
+                Some((172.93, 1.35, 172.93, 1.35)),
             )
             .await
         });
@@ -50,6 +55,7 @@ fn bench_get_hrefs(c: &mut Criterion) {
 
             let results = vec![dataset::MatchingItems {
                 title: "Test Collection".to_string(),
+                collection: Collection::new("an-id", "a description"),
                 items,
             }];
 
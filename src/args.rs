@@ -1,5 +1,7 @@
-use crate::linz_s3_filter::dataset;
-use clap::{builder::ValueParser, command, Args, Parser, Subcommand};
+use crate::linz_s3_filter::{dataset, dataset::PropertyFilter, utils};
+use crate::progress::ProgressFormat;
+use crate::selection::IndexSelection;
+use clap::{builder::ValueParser, Args, Parser, Subcommand, ValueEnum};
 
 /// Enum for search mode.
 #[derive(Parser)]
@@ -12,47 +14,251 @@ use clap::{builder::ValueParser, command, Args, Parser, Subcommand};
 )]
 #[command(propagate_version = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// Set the log level (e.g., error, warn, info, debug, trace).
+    #[arg(short, long, global = true, default_value = "info", value_parser = log_level_parser())]
+    pub log_level: String,
+    /// How to show progress. "json" writes newline-delimited JSON events (search, match, download, file_done) to stderr, with the log lines as log events, or to --progress-file.
+    #[arg(long, global = true, value_enum, default_value_t = ProgressFormat::Human)]
+    pub progress: ProgressFormat,
+    /// Write the JSON progress events to this file instead of stderr.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub progress_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Search a bucket, pick datasets from the matches and download their tiles.
+    #[command(allow_negative_numbers = true)]
+    Search(SearchArgs),
+    /// Download datasets by collection id, without searching or prompting.
+    #[command(allow_negative_numbers = true)]
+    Download(DownloadArgs),
+    /// List the collections of a bucket with their extents and number of items, without fetching the items.
+    List(ListArgs),
+    /// Show the metadata of a collection.
+    Info(InfoArgs),
+    /// Write the extents of the collections of a bucket to a GeoJSON, FlatGeobuf or KML file, or all their items to a stac-geoparquet file.
+    #[command(allow_negative_numbers = true)]
+    Export(ExportArgs),
+    /// Report datasets added, removed or updated since the last run.
+    #[command(allow_negative_numbers = true)]
+    Changes(ChangesArgs),
+    /// Inspect datasets downloaded into a cache directory.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Show or create the configuration file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+/// The bucket to read and how hard to hit it.
+#[derive(Args)]
+pub struct CatalogArgs {
     /// The dataset bucket to search (e.g., imagery or elevation).
     pub bucket: dataset::BucketName,
+    /// Make go brrrr. Will spawn multiple threads to download tiles concurrently by the provided multiplier. If not provided, the default is 1 thread per CPU core.
+    #[arg(short, long)]
+    pub thread_multiplier: Option<usize>,
+    /// Fail if any collection or item cannot be read, instead of carrying on with incomplete results. Timeouts and retries are set by "timeout_secs" and "retries" in config.toml.
+    #[arg(long)]
+    pub strict: bool,
+}
+
+/// Which collections of the bucket to consider.
+#[derive(Args)]
+pub struct CollectionFilterArgs {
+    /// Filter by collection name. Can be used multiple times, will match any of the provided names.
+    #[arg(short = 'n', long)]
+    pub include_collection_name: Option<Vec<String>>,
+    /// Exclude collections by name. Can be used multiple times, will exclude any of the provided names. Exclusion takes precedence over inclusion "include_collection_name" filter.
+    #[arg(short = 'x', long)]
+    pub exclude_collection_name: Option<Vec<String>>,
+    /// Filter by a collection field, e.g. "linz:region=otago" or "linz:geospatial_category=dem". Fields missing at the top level are looked up in the collection summaries. Can be used multiple times, collections must match all of them.
+    #[arg(short = 'p', long = "property", value_name = "KEY=VALUE", value_parser = property_filter_parser())]
+    pub properties: Vec<PropertyFilter>,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
     /// Search mode: "coordinate" for lat/lon range, "area" for search by approx height/width in m.
     #[command(subcommand)]
     pub spatial_filter: Option<SpatialFilter>,
     #[command(flatten)]
-    pub download_args: DownloadArgs,
+    pub collection_filter: CollectionFilterArgs,
+    #[command(flatten)]
+    pub selection: SelectionArgs,
+    /// Just print the URLs.
+    #[arg(short, long, conflicts_with = "TransferArgs")]
+    pub disable_download: bool,
+    /// Write the item footprints of the selected datasets to a GeoJSON (.geojson), FlatGeobuf (.fgb) or KML (.kml) file, with title, date, resolution and asset URL. A .parquet file gets the whole items as stac-geoparquet.
+    #[arg(short, long, value_name = "FILE")]
+    pub export: Option<PathBuf>,
+    /// Write the tiles of the selected datasets to a GeoPackage, one layer per dataset, with asset URL, local path once downloaded, size and date.
+    #[arg(long, value_name = "FILE")]
+    pub tile_index: Option<PathBuf>,
+    #[command(flatten)]
+    pub transfer: TransferArgs,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+pub struct SelectionArgs {
     /// Automatically select the first dataset listed. Datesets are ordered by resolution first, and within each resolution level, alphabetically.
-    #[arg(short = 'f', group = "auto_select", long)]
+    #[arg(short = 'f', long)]
     pub by_first_index: bool,
-    /// Automatically select the nth dataset found. Will default to the first if not specified.
-    #[arg(short = 'i', long, group = "auto_select")]
-    pub by_index: Option<usize>,
+    /// Automatically select datasets by index. Accepts a single index or a list of indices and ranges, e.g. "0,2,5-7".
+    #[arg(short = 'i', long, value_parser = index_selection_parser())]
+    pub by_index: Option<IndexSelection>,
+    /// Automatically select datasets by collection id. Can be used multiple times. Unlike indices, ids do not change when the sort order or the available datasets change.
+    #[arg(long)]
+    pub collection_id: Option<Vec<String>>,
     /// Automatically select all datasets. Useful for downloading all datasets that meet the search criteria.
-    #[arg(short = 'a', long, group = "auto_select")]
+    #[arg(short = 'a', long)]
     pub by_all: bool,
     /// Automatically select the dataset with the most tiles. Can be useful for downloading the dataset with the highest area of coverage, however this is not always the case.
-    #[arg(short = 's', group = "auto_select", long)]
+    #[arg(short = 's', long)]
     pub by_size: bool,
-    /// Filter by collection name. Can be used multiple times, will match any of the provided names.
-    #[arg(short = 'n', long)]
-    pub include_collection_name: Option<Vec<String>>,
-    /// Exclude collections by name. Can be used multiple times, will exclude any of the provided names. Exclusion takes precedence over inclusion "include_collection_name" filter.
-    #[arg(short = 'x', long)]
-    pub exclude_collection_name: Option<Vec<String>>,
-    /// Set the log level (e.g., error, warn, info, debug, trace).
-    #[arg(short, long, default_value = "info", value_parser = log_level_parser())]
-    pub log_level: String,
-    /// Make go brrrr. Will spawn multiple threads to download tiles concurrently by the provided multiplier. If not provided, the default is 1 thread per CPU core.
-    #[arg(short, long)]
-    pub thread_multiplier: Option<usize>,
+    /// Always use the plain numbered prompt instead of the interactive table, even in a terminal.
+    #[arg(long)]
+    pub no_tui: bool,
 }
+
 #[derive(Args)]
-#[group(multiple = false)]
 pub struct DownloadArgs {
-    /// Just print the URLs. Cannot be used with --cache.
-    #[arg(short, long)]
-    pub disable_download: bool,
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
+    /// Only download the tiles within this area.
+    #[command(subcommand)]
+    pub spatial_filter: Option<SpatialFilter>,
+    /// Id of a collection to download. Can be used multiple times.
+    #[arg(long = "collection-id", required = true)]
+    pub collection_ids: Vec<String>,
+    /// Write the tiles to a GeoPackage, one layer per dataset, with asset URL, local path, size and date.
+    #[arg(long, value_name = "FILE")]
+    pub tile_index: Option<PathBuf>,
+    #[command(flatten)]
+    pub transfer: TransferArgs,
+}
+
+/// Where and how tiles are downloaded.
+#[derive(Args)]
+pub struct TransferArgs {
     /// Cache directory for downloaded tiles.
     #[arg(short, long, value_parser = folder_parser())]
     pub cache: Option<String>,
+    /// Refuse to download more than this amount, e.g. "500M" or "20G".
+    #[arg(long, value_parser = byte_size_parser())]
+    pub max_bytes: Option<u64>,
+    /// Ask for confirmation before downloading more than this amount.
+    #[arg(long, default_value = "10G", value_parser = byte_size_parser())]
+    pub confirm_above: u64,
+    /// Maximum number of files downloaded at the same time.
+    #[arg(long, default_value_t = 8, value_parser = positive_usize_parser())]
+    pub max_downloads: usize,
+    /// Limit the combined download rate in bytes per second, e.g. "20M".
+    #[arg(long, value_parser = byte_size_parser())]
+    pub limit_rate: Option<u64>,
+    /// Sync mode: download tiles again if their checksum or "updated" timestamp changed upstream, instead of skipping every existing file.
+    #[arg(long)]
+    pub sync: bool,
+    /// With --sync, delete local tiles recorded in the dataset manifest that are no longer part of the collection.
+    #[arg(long, requires = "sync")]
+    pub prune: bool,
+    /// Download without asking for confirmation, regardless of --confirm-above.
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
+    #[command(flatten)]
+    pub collection_filter: CollectionFilterArgs,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
+    /// Id or title of the collection.
+    pub collection: String,
+    /// Print the metadata as text or JSON.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    /// Only show the collection metadata. Skips fetching every item to total the asset sizes, CRS and resolutions.
+    #[arg(long)]
+    pub skip_items: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
+    /// The file to write, a .geojson, .fgb or .kml file, or a .parquet file for stac-geoparquet.
+    pub output: PathBuf,
+    /// Only export collections covering this area.
+    #[command(subcommand)]
+    pub spatial_filter: Option<SpatialFilter>,
+    #[command(flatten)]
+    pub collection_filter: CollectionFilterArgs,
+}
+
+#[derive(Args)]
+pub struct ChangesArgs {
+    #[command(flatten)]
+    pub catalog: CatalogArgs,
+    /// Only report datasets covering this area.
+    #[command(subcommand)]
+    pub spatial_filter: Option<SpatialFilter>,
+    /// Keep running and check for changes at this interval, e.g. "30m", "6h" or "1d".
+    #[arg(long, value_parser = duration_parser())]
+    pub watch: Option<Duration>,
+    /// The file the catalog snapshot is kept in. Defaults to "linz_s3_<bucket>_snapshot.json" in the current directory.
+    #[arg(long)]
+    pub snapshot: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List the datasets in a cache directory with their number of tiles and size.
+    List {
+        /// The cache directory.
+        #[arg(value_parser = folder_parser())]
+        dir: String,
+    },
+    /// Check the downloaded tiles against the checksums in their manifests.
+    Verify {
+        /// The cache directory.
+        #[arg(value_parser = folder_parser())]
+        dir: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration in use.
+    Show,
+    /// Write the default configuration to the configuration file.
+    Init {
+        /// Overwrite an existing configuration file.
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -130,9 +336,37 @@ impl SpatialFilterParams {
             },
         }
     }
+
+    /// Bounding box of the filter as (lon_min, lat_min, lon_max, lat_max).
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        let (lat1, lon1, lat2, lon2) = match self.width_m_opt {
+            Some(width_m) => utils::get_coordinate_from_dimension(
+                self.lat1,
+                self.lon1,
+                width_m,
+                self.height_m_opt.unwrap_or(width_m),
+            ),
+            None => (
+                self.lat1,
+                self.lon1,
+                self.lat2_opt.unwrap_or(self.lat1),
+                self.lon2_opt.unwrap_or(self.lon1),
+            ),
+        };
+        (
+            lon1.min(lon2),
+            lat1.min(lat2),
+            lon1.max(lon2),
+            lat1.max(lat2),
+        )
+    }
 }
 
-use std::{path::Path, str::FromStr};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
 fn latitude_parser() -> ValueParser {
     ValueParser::new(|s: &str| {
@@ -173,9 +407,133 @@ fn folder_parser() -> ValueParser {
     })
 }
 
+/// Parses a byte size such as "1024", "20M" or "1.5GB". Units are powers of 1024.
+pub fn parse_byte_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    let value = f64::from_str(number.trim()).map_err(|_| format!("Invalid size: {}", s))?;
+    if value < 0.0 || !value.is_finite() {
+        return Err(format!("Invalid size: {}", s));
+    }
+    Ok((value * multiplier as f64) as u64)
+}
+
+/// Parses a duration such as "90", "30m", "6h" or "1d". A bare number is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        Some('d') => (&s[..s.len() - 1], 24 * 60 * 60),
+        _ => (s, 1),
+    };
+    match u64::from_str(number.trim()) {
+        Ok(value) if value > 0 => value
+            .checked_mul(multiplier)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("Invalid duration: {}", s)),
+        _ => Err(format!("Invalid duration: {}", s)),
+    }
+}
+
+fn duration_parser() -> ValueParser {
+    ValueParser::new(parse_duration)
+}
+
+fn byte_size_parser() -> ValueParser {
+    ValueParser::new(parse_byte_size)
+}
+
+fn positive_usize_parser() -> ValueParser {
+    ValueParser::new(|s: &str| match usize::from_str(s) {
+        Ok(val) if val > 0 => Ok(val),
+        _ => Err(format!("Must be a whole number greater than zero: {}", s)),
+    })
+}
+
+fn property_filter_parser() -> ValueParser {
+    ValueParser::new(|s: &str| s.parse::<PropertyFilter>())
+}
+
+fn index_selection_parser() -> ValueParser {
+    ValueParser::new(|s: &str| s.parse::<IndexSelection>())
+}
+
 fn log_level_parser() -> ValueParser {
     ValueParser::new(|s: &str| match s.to_lowercase().as_str() {
         "error" | "warn" | "info" | "debug" | "trace" => Ok(s.to_string()),
         _ => Err(format!("Invalid log level: {}", s)),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_is_valid() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_search_arguments() {
+        let cli = Cli::try_parse_from([
+            "linz_s3",
+            "search",
+            "elevation",
+            "-i",
+            "0-1",
+            "--sync",
+            "coordinate",
+            "-41.3",
+            "174.7",
+        ])
+        .unwrap();
+        let Command::Search(args) = cli.command else {
+            panic!("expected the search command");
+        };
+        assert!(args.transfer.sync);
+        assert!(args.selection.by_index.is_some());
+        assert!(matches!(
+            args.spatial_filter,
+            Some(SpatialFilter::Coordinate { lat1, .. }) if lat1 == -41.3
+        ));
+
+        // Only one way of picking datasets, and no download settings when not downloading.
+        assert!(Cli::try_parse_from(["linz_s3", "search", "elevation", "-f", "-a"]).is_err());
+        assert!(Cli::try_parse_from(["linz_s3", "search", "elevation", "-d", "--sync"]).is_err());
+        assert!(Cli::try_parse_from(["linz_s3", "download", "elevation"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("20M"), Ok(20 * 1024 * 1024));
+        assert_eq!(parse_byte_size("1.5GB"), Ok(1536 * 1024 * 1024));
+        assert_eq!(parse_byte_size("2kib"), Ok(2048));
+        assert_eq!(parse_byte_size("1T"), Ok(1 << 40));
+        assert!(parse_byte_size("").is_err());
+        assert!(parse_byte_size("-1M").is_err());
+        assert!(parse_byte_size("ten").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("6h"), Ok(Duration::from_secs(21600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("999999999999999999d").is_err());
+    }
+}
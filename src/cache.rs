@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::MyError;
+use crate::manifest::{file_checksum, Manifest, ManifestEntry};
+
+/// A dataset folder in a cache directory, as recorded by its manifest.
+#[derive(Debug)]
+pub struct CachedDataset {
+    pub folder: PathBuf,
+    pub title: String,
+    pub collection_id: String,
+    pub manifest: Manifest,
+}
+
+impl CachedDataset {
+    /// Total size of the recorded tiles in bytes.
+    pub fn bytes(&self) -> u64 {
+        self.manifest.tiles.iter().map(|entry| entry.size).sum()
+    }
+}
+
+/// The dataset folders of a cache directory that have a manifest, sorted by folder name.
+pub fn cached_datasets(dir: &Path) -> Result<Vec<CachedDataset>, MyError> {
+    let mut datasets = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let folder = entry?.path();
+        if !folder.is_dir() {
+            continue;
+        }
+        let manifest = Manifest::load(&folder)?;
+        let Some(first) = manifest.tiles.first() else {
+            continue;
+        };
+        datasets.push(CachedDataset {
+            title: first.collection_title.clone(),
+            collection_id: first.collection_id.clone(),
+            folder,
+            manifest,
+        });
+    }
+    datasets.sort_by(|a, b| a.folder.cmp(&b.folder));
+    Ok(datasets)
+}
+
+/// What is wrong with a recorded tile.
+#[derive(Debug, PartialEq)]
+pub enum TileProblem {
+    Missing,
+    ChecksumMismatch,
+}
+
+/// Checks every tile recorded for a dataset against its size and checksum.
+pub async fn verify_dataset(
+    dataset: &CachedDataset,
+) -> Result<Vec<(&ManifestEntry, TileProblem)>, MyError> {
+    let mut problems = vec![];
+    for entry in &dataset.manifest.tiles {
+        let path = entry.path_in(&dataset.folder);
+        if !path.exists() {
+            problems.push((entry, TileProblem::Missing));
+            continue;
+        }
+        let (size, checksum) = file_checksum(&path).await?;
+        if size != entry.size || checksum != entry.checksum {
+            problems.push((entry, TileProblem::ChecksumMismatch));
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(local_path: &str) -> ManifestEntry {
+        ManifestEntry {
+            source_url: format!("https://example.com/{}", local_path),
+            local_path: local_path.to_string(),
+            size: 11,
+            checksum: "1220b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+                .to_string(),
+            item_id: "item".to_string(),
+            item_href: None,
+            updated: None,
+            collection_id: "collection".to_string(),
+            collection_title: "Collection".to_string(),
+            license: "CC-BY-4.0".to_string(),
+            downloaded_at: "2025-06-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_verify() {
+        let dir = tempdir().unwrap();
+        let folder = dir.path().join("Collection");
+        std::fs::create_dir(&folder).unwrap();
+        std::fs::create_dir(dir.path().join("not a dataset")).unwrap();
+        std::fs::write(folder.join("good.tif"), "hello world").unwrap();
+        std::fs::write(folder.join("bad.tif"), "hello there").unwrap();
+        let mut manifest = Manifest::default();
+        for name in ["old/location/good.tif", "bad.tif", "missing.tif"] {
+            manifest.upsert(entry(name));
+        }
+        manifest.write(&folder).unwrap();
+
+        let datasets = cached_datasets(dir.path()).unwrap();
+        assert_eq!(datasets.len(), 1);
+        assert_eq!(datasets[0].collection_id, "collection");
+        assert_eq!(datasets[0].bytes(), 33);
+
+        let problems = verify_dataset(&datasets[0]).await.unwrap();
+        let problems: Vec<_> = problems
+            .iter()
+            .map(|(entry, problem)| (entry.local_path.as_str(), problem))
+            .collect();
+        assert_eq!(
+            problems,
+            [
+                ("bad.tif", &TileProblem::ChecksumMismatch),
+                ("missing.tif", &TileProblem::Missing)
+            ]
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use chrono::Utc;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use stac::Collection;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::error::MyError;
+use crate::linz_s3_filter::{dataset::BucketName, linz_bucket::LinzBucket};
+
+/// What is remembered about a collection between runs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CollectionSnapshot {
+    pub id: String,
+    pub title: String,
+    /// Spatial extent as `[lon_min, lat_min, lon_max, lat_max]` boxes.
+    pub bbox: Vec<[f64; 4]>,
+    /// Temporal extent as RFC 3339 start and end dates, open ends as `None`.
+    pub interval: Vec<[Option<String>; 2]>,
+    /// The collection's `updated` timestamp, if it has one.
+    pub updated: Option<String>,
+}
+
+impl CollectionSnapshot {
+    pub fn new(collection: &Collection) -> Self {
+        CollectionSnapshot {
+            id: collection.id.clone(),
+            title: collection
+                .title
+                .clone()
+                .unwrap_or_else(|| collection.id.clone()),
+            bbox: collection
+                .extent
+                .spatial
+                .bbox
+                .iter()
+                .map(|bbox| [bbox.xmin(), bbox.ymin(), bbox.xmax(), bbox.ymax()])
+                .collect(),
+            interval: collection
+                .extent
+                .temporal
+                .interval
+                .iter()
+                .map(|interval| interval.map(|date| date.map(|date| date.to_rfc3339())))
+                .collect(),
+            updated: collection
+                .additional_fields
+                .get("updated")
+                .and_then(|value| value.as_str())
+                .map(str::to_string),
+        }
+    }
+
+    /// Whether any box of the spatial extent intersects (lon_min, lat_min, lon_max, lat_max).
+    pub fn intersects(&self, (lon_min, lat_min, lon_max, lat_max): (f64, f64, f64, f64)) -> bool {
+        self.bbox.iter().any(|bbox| {
+            bbox[0] <= lon_max && bbox[2] >= lon_min && bbox[1] <= lat_max && bbox[3] >= lat_min
+        })
+    }
+}
+
+/// The collections of a bucket catalog at a point in time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CatalogSnapshot {
+    /// RFC 3339 timestamp of when the snapshot was taken.
+    pub taken_at: String,
+    pub collections: Vec<CollectionSnapshot>,
+}
+
+impl CatalogSnapshot {
+    pub fn new(collections: &[Collection]) -> Self {
+        CatalogSnapshot {
+            taken_at: Utc::now().to_rfc3339(),
+            collections: collections.iter().map(CollectionSnapshot::new).collect(),
+        }
+    }
+
+    /// Reads a snapshot file, `None` if there is none yet.
+    pub fn load(path: &Path) -> Result<Option<Self>, MyError> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Keeps the collections of `previous` that are missing from this snapshot, for when
+    /// some collections could not be read and missing ones may not have been removed.
+    pub fn keep_missing(&mut self, previous: &CatalogSnapshot) {
+        let ids: HashSet<String> = self
+            .collections
+            .iter()
+            .map(|collection| collection.id.clone())
+            .collect();
+        self.collections.extend(
+            previous
+                .collections
+                .iter()
+                .filter(|collection| !ids.contains(&collection.id))
+                .cloned(),
+        );
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), MyError> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Collections added, removed and updated since `previous`. With an AOI as
+    /// (lon_min, lat_min, lon_max, lat_max), only collections whose old or new
+    /// extent intersects it are reported.
+    pub fn changes_since(
+        &self,
+        previous: &CatalogSnapshot,
+        aoi: Option<(f64, f64, f64, f64)>,
+    ) -> CatalogChanges {
+        let relevant =
+            |collection: &CollectionSnapshot| aoi.is_none_or(|aoi| collection.intersects(aoi));
+        let before: HashMap<&str, &CollectionSnapshot> = previous
+            .collections
+            .iter()
+            .map(|collection| (collection.id.as_str(), collection))
+            .collect();
+        let after: HashMap<&str, &CollectionSnapshot> = self
+            .collections
+            .iter()
+            .map(|collection| (collection.id.as_str(), collection))
+            .collect();
+
+        let mut changes = CatalogChanges::default();
+        for collection in &self.collections {
+            match before.get(collection.id.as_str()) {
+                None if relevant(collection) => changes.added.push(collection.clone()),
+                Some(&old) if old != collection && (relevant(old) || relevant(collection)) => {
+                    changes.updated.push(collection.clone())
+                }
+                _ => {}
+            }
+        }
+        for collection in &previous.collections {
+            if !after.contains_key(collection.id.as_str()) && relevant(collection) {
+                changes.removed.push(collection.clone());
+            }
+        }
+        changes
+    }
+}
+
+/// Differences between two catalog snapshots.
+#[derive(Debug, Default)]
+pub struct CatalogChanges {
+    pub added: Vec<CollectionSnapshot>,
+    pub removed: Vec<CollectionSnapshot>,
+    /// Collections whose title, extent or `updated` timestamp changed, as they are now.
+    pub updated: Vec<CollectionSnapshot>,
+}
+
+impl CatalogChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.updated.is_empty()
+    }
+
+    /// Prints one tab separated line per change: kind, collection id and title.
+    pub fn print(&self) {
+        for (kind, collections) in [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("updated", &self.updated),
+        ] {
+            for collection in collections {
+                println!("{}\t{}\t{}", kind, collection.id, collection.title);
+            }
+        }
+    }
+}
+
+/// Snapshots the bucket catalog, prints the changes since the snapshot stored at `path`
+/// and replaces it. The first run only records the snapshot and returns `None`.
+///
+/// If any collection could not be read, collections missing since the previous snapshot
+/// are kept in it rather than reported as removed, until a run reads the whole catalog.
+/// With `strict`, the check fails instead and the previous snapshot is kept.
+pub async fn check_for_changes(
+    bucket: BucketName,
+    path: &Path,
+    aoi: Option<(f64, f64, f64, f64)>,
+    concurrency_multiplier: Option<usize>,
+    strict: bool,
+) -> Result<Option<CatalogChanges>, MyError> {
+    let linz_bucket = LinzBucket::initialise_catalog(bucket, concurrency_multiplier).await?;
+    if strict {
+        linz_bucket.failures.check()?;
+    }
+    let mut current = CatalogSnapshot::new(&linz_bucket.collections);
+    let previous = CatalogSnapshot::load(path)?;
+    if let Some(previous) = &previous {
+        let failed = linz_bucket.failures.collections().len();
+        if failed > 0 {
+            warn!(
+                "{} collections could not be read, not reporting removed collections",
+                failed
+            );
+            current.keep_missing(previous);
+        }
+    }
+    current.write(path)?;
+    let Some(previous) = previous else {
+        info!(
+            "No previous snapshot, recorded {} collections in {}",
+            current.collections.len(),
+            path.display()
+        );
+        return Ok(None);
+    };
+    let changes = current.changes_since(&previous, aoi);
+    if changes.is_empty() {
+        info!("No changes since {}", previous.taken_at);
+    } else {
+        info!(
+            "Since {}: {} added, {} removed, {} updated",
+            previous.taken_at,
+            changes.added.len(),
+            changes.removed.len(),
+            changes.updated.len()
+        );
+        changes.print();
+    }
+    Ok(Some(changes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn collection(id: &str, bbox: [f64; 4], updated: Option<&str>) -> CollectionSnapshot {
+        CollectionSnapshot {
+            id: id.to_string(),
+            title: format!("{} title", id),
+            bbox: vec![bbox],
+            interval: vec![[Some("2024-01-01T00:00:00+00:00".to_string()), None]],
+            updated: updated.map(str::to_string),
+        }
+    }
+
+    fn snapshot(collections: Vec<CollectionSnapshot>) -> CatalogSnapshot {
+        CatalogSnapshot {
+            taken_at: "2025-06-01T00:00:00+00:00".to_string(),
+            collections,
+        }
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let wellington = [174.7, -41.4, 174.9, -41.2];
+        let auckland = [174.6, -37.0, 174.9, -36.7];
+        let previous = snapshot(vec![
+            collection("kept", wellington, Some("2025-01-01")),
+            collection("updated", wellington, Some("2025-01-01")),
+            collection("removed", auckland, None),
+        ]);
+        let current = snapshot(vec![
+            collection("kept", wellington, Some("2025-01-01")),
+            collection("updated", wellington, Some("2025-05-01")),
+            collection("added", wellington, None),
+        ]);
+
+        let changes = current.changes_since(&previous, None);
+        assert_eq!(changes.added[0].id, "added");
+        assert_eq!(changes.removed[0].id, "removed");
+        assert_eq!(changes.updated.len(), 1);
+        assert_eq!(changes.updated[0].updated.as_deref(), Some("2025-05-01"));
+
+        let changes = current.changes_since(&previous, Some((174.7, -41.3, 174.8, -41.25)));
+        assert_eq!(changes.added.len(), 1);
+        assert_eq!(changes.updated.len(), 1);
+        assert!(changes.removed.is_empty());
+
+        assert!(current.changes_since(&current, None).is_empty());
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        assert!(CatalogSnapshot::load(&path).unwrap().is_none());
+
+        let current = snapshot(vec![collection("a", [0.0, 0.0, 1.0, 1.0], None)]);
+        current.write(&path).unwrap();
+        let loaded = CatalogSnapshot::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.collections, current.collections);
+    }
+
+    #[test]
+    fn test_keep_missing() {
+        let bbox = [0.0, 0.0, 1.0, 1.0];
+        let previous = snapshot(vec![
+            collection("a", bbox, None),
+            collection("b", bbox, None),
+        ]);
+        let mut current = snapshot(vec![
+            collection("a", bbox, Some("2025-05-01")),
+            collection("c", bbox, None),
+        ]);
+        current.keep_missing(&previous);
+
+        let ids: Vec<_> = current.collections.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["a", "c", "b"]);
+        assert_eq!(
+            current.collections[0].updated.as_deref(),
+            Some("2025-05-01")
+        );
+        let changes = current.changes_since(&previous, None);
+        assert!(changes.removed.is_empty());
+        assert_eq!(changes.added.len(), 1);
+    }
+}
@@ -1,129 +1,548 @@
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::{DatasetMatch, Tile};
+use crate::manifest::{file_checksum, ChecksumWriter, Manifest, ManifestEntry};
+use crate::progress::{self, ProgressEvent};
+use crate::rate_limit::RateLimiter;
+use crate::sync;
+use crate::tile_index;
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use log::{debug, info};
-use reqwest::get;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use log::{debug, info, warn};
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::Client;
 use sanitize_filename::sanitize;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::fs::{self, File};
 use tokio::io::AsyncWriteExt;
-use tokio::sync::oneshot;
-use tokio::{signal, task};
+use tokio::signal;
 
+/// Number of concurrent HEAD requests used to look up tile sizes.
+const HEAD_CONCURRENCY: usize = 16;
+
+/// Options controlling what happens to the tiles of the selected datasets.
+pub struct DownloadOptions {
+    /// Download the tiles. If false, only their URLs are printed.
+    pub download: bool,
+    /// Directory to download into. Defaults to the current directory.
+    pub cache: Option<PathBuf>,
+    /// Refuse to download more than this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Ask for confirmation before downloading more than this many bytes.
+    pub confirm_above: u64,
+    /// Do not ask for confirmation.
+    pub assume_yes: bool,
+    /// Maximum number of files downloaded at the same time.
+    pub max_downloads: usize,
+    /// Combined download rate limit in bytes per second.
+    pub limit_rate: Option<u64>,
+    /// Download tiles again if they changed upstream, instead of skipping every existing file.
+    pub sync: bool,
+    /// When syncing, delete local tiles that are no longer part of their collection.
+    pub prune: bool,
+    /// Write the tiles to this GeoPackage, one layer per dataset.
+    pub tile_index: Option<PathBuf>,
+    /// Client shared by all requests.
+    pub client: Client,
+}
+
+/// Prints and optionally downloads the tiles of the selected datasets.
+/// Tiles shared between selected datasets are only handled once, in the folder of the first dataset listing them.
+///
+/// Fails with `MyError::DownloadsFailed` if any tile could not be downloaded, after the others are done, and with
+/// `MyError::Cancelled` if the downloads are interrupted.
 pub async fn process_tile_list(
-    tile_list: &[(Vec<String>, String)],
-    index: usize,
-    download: bool,
-    cache_opt: &Option<PathBuf>,
-) {
-    let mut tasks = vec![];
+    tile_list: &[DatasetMatch],
+    indices: &[usize],
+    options: &DownloadOptions,
+) -> Result<(), MyError> {
+    let mut seen = HashSet::new();
     let mut cache_count = 0;
-    if download {
-        let multiprogressbar = MultiProgress::new();
+    if options.download {
+        let output_root = options.cache.clone().unwrap_or_else(|| PathBuf::from("."));
+        let mut entries = vec![];
+        for &index in indices {
+            let subfolder = sanitize(tile_list[index].title.clone());
+            let output_folder = output_root.join(&subfolder);
+            let manifest = if options.sync {
+                Manifest::load(&output_folder).unwrap_or_else(|e| {
+                    e.report();
+                    Manifest::default()
+                })
+            } else {
+                Manifest::default()
+            };
+            for tile in &tile_list[index].tiles {
+                if !seen.insert(&tile.href) {
+                    debug!("Tile already selected in another dataset: {}", tile.href);
+                    continue;
+                }
+                let file_name = tile_file_name(&tile.href)?;
+                let current_path = output_folder.join(file_name);
+                let needs_download = if !current_path.exists() {
+                    true
+                } else if options.sync {
+                    let recorded = manifest.get(&current_path.display().to_string());
+                    let outdated = sync::is_outdated(tile, &current_path, recorded).await;
+                    if outdated {
+                        info!("{} changed upstream, downloading again", file_name);
+                    }
+                    outdated
+                } else {
+                    false
+                };
+                entries.push((
+                    index,
+                    tile,
+                    output_folder.clone(),
+                    current_path,
+                    needs_download,
+                ));
+            }
+        }
+        let (bytes, unknown) = entries
+            .iter()
+            .filter(|(_, _, _, _, needs_download)| *needs_download)
+            .fold((0, 0), |(bytes, unknown), (_, tile, _, _, _)| {
+                match tile.size {
+                    Some(size) => (bytes + size, unknown),
+                    None => (bytes, unknown + 1),
+                }
+            });
+        if !confirm_download(bytes, unknown, &output_root, options)? {
+            return Ok(());
+        }
 
-        info!("Starting downloads...");
+        let multiprogressbar = progress::bars().clone();
+        let overall = multiprogressbar.add(ProgressBar::new(0));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{bar:40.green/white}] {pos}/{len} files ({eta})")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        let rate_limiter = options
+            .limit_rate
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
 
-        // Create a channel to signal cancellation
-        let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+        let bytes_downloaded = Arc::new(AtomicU64::new(0));
 
-        // Spawn a task to listen for the interrupt signal
-        tokio::spawn(async move {
-            signal::ctrl_c().await.expect("Failed to listen for event");
-            let _ = cancel_tx.send(());
-        });
+        info!("Starting downloads...");
 
-        for tile_url in &tile_list[index].0 {
-            let multiprogressbar = multiprogressbar.clone();
-            let url = tile_url.to_string();
-
-            let subfolder = sanitize(tile_list[index].1.clone());
-            let output_folder = cache_opt
-                .clone()
-                .unwrap_or_else(|| PathBuf::from("."))
-                .join(&subfolder);
-            let file_name = Path::new(&url).file_name().unwrap().to_str().unwrap();
-            let current_path = output_folder.join(file_name);
+        let ctx = DownloadContext {
+            client: options.client.clone(),
+            multi_progress: multiprogressbar.clone(),
+            overall: overall.clone(),
+            rate_limiter,
+            bytes_downloaded: bytes_downloaded.clone(),
+        };
+        let mut records = vec![];
+        let mut pending = vec![];
+        for (index, tile, output_folder, current_path, needs_download) in entries {
             // Print file to stdout
             println!("{}", current_path.display());
             // Check if the file already exists in the cache or current directory
-            if current_path.exists() {
+            if !needs_download {
                 debug!(
                     "File already exists in current directory: {}",
                     current_path.display()
                 );
                 cache_count += 1;
-
+                progress::stats().add_cache_hit();
+                records.push((index, tile, output_folder, current_path));
                 continue;
             }
             // Create the subfolder if it doesn't exist
-            fs::create_dir_all(&output_folder).await.unwrap();
-            tasks.push(task::spawn(async move {
-                download_file(&url, current_path, multiprogressbar).await;
-            }));
+            fs::create_dir_all(&output_folder).await?;
+            overall.inc_length(1);
+            pending.push((index, tile, output_folder, current_path));
         }
-        let download_count = tasks.len();
+        let download_count = pending.len();
+        // At most `max_downloads` files are downloaded at once, in one task
+        let mut downloads = futures::stream::iter(pending)
+            .map(|record| {
+                let ctx = &ctx;
+                async move {
+                    let (url, path) = (&record.1.href, &record.3);
+                    let result = download_file(url, path.clone(), ctx).await;
+                    ctx.overall.inc(1);
+                    progress::emit(&ProgressEvent::FileDone {
+                        url,
+                        path: &path.display().to_string(),
+                        bytes: result.as_ref().ok().map(|(size, _)| *size),
+                        error: result.as_ref().err().map(|e| e.to_string()),
+                    });
+                    let result = result.map_err(|e| MyError::DownloadError {
+                        url: url.clone(),
+                        source: Box::new(e),
+                    });
+                    (record, result)
+                }
+            })
+            .buffer_unordered(options.max_downloads.max(1));
+        let mut downloaded = vec![];
+        let mut failures = vec![];
+        let report_download = {
+            let (bytes_downloaded, overall) = (bytes_downloaded.clone(), overall.clone());
+            move || {
+                progress::emit(&ProgressEvent::Download {
+                    bytes_downloaded: bytes_downloaded.load(Ordering::Relaxed),
+                    bytes_total: bytes,
+                    files_done: overall.position() as usize,
+                    files_total: download_count,
+                })
+            }
+        };
+        let reporting = progress::is_json().then(|| {
+            let report_download = report_download.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    report_download();
+                }
+            })
+        });
 
-        // Wait for tasks to complete or cancellation signal
-        tokio::select! {
+        // Wait for the downloads to complete or the interrupt signal, listened for only while
+        // this call downloads
+        let interrupted = tokio::select! {
             _ = async {
-                for task in tasks {
-                    task.await.unwrap();
+                while let Some((record, result)) = downloads.next().await {
+                    match result {
+                        Ok(result) => downloaded.push((record, result)),
+                        Err(e) => {
+                            e.report();
+                            failures.push(e);
+                        }
+                    }
                 }
             } => {
                 info!(
                     "{} files found in cache, {} files downloaded",
                     cache_count, download_count
                 );
+                false
             },
-            _ = cancel_rx => {
-            info!("Download process interrupted by user");
+            Ok(()) = signal::ctrl_c() => {
+                info!("Download process interrupted by user");
+                true
+            }
+        };
+        // Stops the downloads still in flight after an interrupt
+        drop(downloads);
+        if let Some(reporting) = reporting {
+            reporting.abort();
+            report_download();
+        }
+        if interrupted {
+            // Only record what finished, don't spend time checksumming cached files.
+            records.clear();
+        }
+        update_manifests(tile_list, records, downloaded).await;
+        if options.prune && !interrupted {
+            let mut pruned = HashSet::new();
+            for &index in indices {
+                let output_folder = output_root.join(sanitize(tile_list[index].title.clone()));
+                if !pruned.insert(output_folder.clone()) {
+                    continue;
+                }
+                match sync::prune_dataset(&output_folder, &tile_list[index].collection) {
+                    Ok(0) => {}
+                    Ok(count) => info!(
+                        "Removed {} tiles no longer in {}",
+                        count, tile_list[index].title
+                    ),
+                    Err(e) => e.report(),
+                }
             }
         }
+        write_tile_index(tile_list, indices, Some(&output_root), options);
+        if interrupted {
+            return Err(MyError::Cancelled);
+        }
+        let failed = failures.len();
+        if let Some(first) = failures.into_iter().next() {
+            return Err(MyError::DownloadsFailed {
+                failed,
+                total: download_count,
+                source: Box::new(first),
+            });
+        }
     } else {
         //Just print the URLs
         info!("Download is disabled, printing URLs only:");
-        for tile_url in &tile_list[index].0 {
-            println!("{}", tile_url);
+        for &index in indices {
+            for tile in &tile_list[index].tiles {
+                if seen.insert(&tile.href) {
+                    println!("{}", tile.href);
+                }
+            }
         }
+        write_tile_index(tile_list, indices, None, options);
     }
+    Ok(())
 }
 
-async fn download_file(url: &str, output_file: PathBuf, multi_progress: MultiProgress) {
-    match get(url).await {
-        Ok(response) => {
-            let total_size = response.content_length().unwrap_or(0);
-
-            let pb = multi_progress.add(ProgressBar::new(total_size));
-            pb.set_style(
-                ProgressStyle::default_bar()
-                    .template(
-                        "{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}",
-                    )
-                    .unwrap()
-                    .progress_chars("#>-"),
-            );
-            let path_str = Path::new(&url)
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string();
-            pb.set_message(path_str.clone());
-            pb.enable_steady_tick(Duration::from_millis(100));
+/// Name of the local file of a tile, the last segment of its URL.
+fn tile_file_name(href: &str) -> Result<&str, MyError> {
+    Path::new(href)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| MyError::InvalidHref(href.to_string()))
+}
+
+/// Writes the GeoPackage tile index if one was asked for.
+fn write_tile_index(
+    tile_list: &[DatasetMatch],
+    indices: &[usize],
+    output_root: Option<&Path>,
+    options: &DownloadOptions,
+) {
+    let Some(path) = &options.tile_index else {
+        return;
+    };
+    match tile_index::write_tile_index(path, tile_list, indices, output_root) {
+        Ok(()) => info!("Wrote tile index to {}", path.display()),
+        Err(e) => e.report(),
+    }
+}
+
+/// Checks the expected download size against `--max-bytes` and the free disk space,
+/// and asks the user to confirm large downloads. Returns whether to go ahead, or an error if the
+/// download does not fit.
+fn confirm_download(
+    bytes: u64,
+    unknown: usize,
+    output_root: &Path,
+    options: &DownloadOptions,
+) -> Result<bool, MyError> {
+    info!("Expected download size: {}", HumanBytes(bytes));
+    if unknown > 0 {
+        warn!(
+            "{} tiles have an unknown size and are not included in the expected download size",
+            unknown
+        );
+    }
+    if let Some(max_bytes) = options.max_bytes {
+        if bytes > max_bytes {
+            return Err(MyError::DownloadTooLarge {
+                size: HumanBytes(bytes).to_string(),
+                limit: HumanBytes(max_bytes).to_string(),
+            });
+        }
+    }
+    match fs4::available_space(output_root) {
+        Ok(available) if bytes > available => {
+            return Err(MyError::InsufficientSpace {
+                folder: output_root.display().to_string(),
+                needed: HumanBytes(bytes).to_string(),
+                available: HumanBytes(available).to_string(),
+            });
+        }
+        Ok(_) => {}
+        Err(e) => warn!(
+            "Could not determine free disk space in {}: {}",
+            output_root.display(),
+            e
+        ),
+    }
+    if bytes > options.confirm_above && !options.assume_yes {
+        eprint!("Download {}? [y/N] ", HumanBytes(bytes));
+        let mut input = String::new();
+        let confirmed = io::stdin().read_line(&mut input).is_ok()
+            && matches!(input.trim().to_lowercase().as_str(), "y" | "yes");
+        if !confirmed {
+            info!("Download canceled.");
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Fills in unknown tile sizes from the `Content-Length` of concurrent HEAD requests.
+pub async fn fill_unknown_sizes(client: &Client, tiles: &mut [Tile]) {
+    futures::stream::iter(tiles.iter_mut().filter(|tile| tile.size.is_none()))
+        .for_each_concurrent(HEAD_CONCURRENCY, |tile| async move {
+            tile.size = content_length(client, &tile.href).await;
+        })
+        .await;
+}
+
+async fn content_length(client: &Client, url: &str) -> Option<u64> {
+    progress::stats().add_request();
+    let response = match client
+        .head(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(e) => {
+            debug!("HEAD request for {} failed: {}", url, e);
+            return None;
+        }
+    };
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
 
-            let mut file = File::create(output_file).await.unwrap();
-            let mut stream = response.bytes_stream();
+type TileRecord<'a> = (usize, &'a Tile, PathBuf, PathBuf);
 
-            while let Some(chunk) = stream.next().await {
-                let chunk = chunk.unwrap();
-                file.write_all(&chunk).await.unwrap();
-                pb.inc(chunk.len() as u64);
+/// Adds downloaded tiles, and cached tiles not recorded yet, to the manifests of their dataset folders.
+async fn update_manifests(
+    tile_list: &[DatasetMatch],
+    cached: Vec<TileRecord<'_>>,
+    downloaded: Vec<(TileRecord<'_>, (u64, String))>,
+) {
+    let now = Utc::now().to_rfc3339();
+    let mut by_folder: BTreeMap<PathBuf, Vec<_>> = BTreeMap::new();
+    let records = cached.into_iter().map(|record| (record, None)).chain(
+        downloaded
+            .into_iter()
+            .map(|(record, result)| (record, Some(result))),
+    );
+    for ((index, tile, folder, path), result) in records {
+        by_folder
+            .entry(folder)
+            .or_default()
+            .push((index, tile, path, result));
+    }
+    for (folder, records) in by_folder {
+        let mut manifest = match Manifest::load(&folder) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                e.report();
+                continue;
             }
+        };
+        let mut changed = false;
+        for (index, tile, path, result) in records {
+            let local_path = path.display().to_string();
+            let (size, checksum, downloaded_at) = match result {
+                Some((size, checksum)) => (size, checksum, now.clone()),
+                None if manifest.get(&local_path).is_some() => continue,
+                None => {
+                    // Cached by a run that did not write a manifest, so the download time is unknown.
+                    let modified = std::fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| DateTime::<Utc>::from(modified).to_rfc3339())
+                        .unwrap_or_else(|_| now.clone());
+                    match file_checksum(&path).await {
+                        Ok((size, checksum)) => (size, checksum, modified),
+                        Err(e) => {
+                            e.report();
+                            continue;
+                        }
+                    }
+                }
+            };
+            let dataset = &tile_list[index];
+            manifest.upsert(ManifestEntry {
+                source_url: tile.href.clone(),
+                local_path,
+                size,
+                checksum,
+                item_id: tile.item_id.clone(),
+                item_href: tile.item_href.clone(),
+                updated: tile.updated.clone(),
+                collection_id: dataset.collection.id.clone(),
+                collection_title: dataset.title.clone(),
+                license: dataset.collection.license.clone(),
+                downloaded_at,
+            });
+            changed = true;
+        }
+        if changed {
+            match manifest.write(&folder) {
+                Ok(()) => debug!("Updated manifest in {}", folder.display()),
+                Err(e) => e.report(),
+            }
+        }
+    }
+}
+
+/// Shared state of the downloads started by one call to `process_tile_list`.
+struct DownloadContext {
+    client: Client,
+    multi_progress: MultiProgress,
+    overall: ProgressBar,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Bytes received by all downloads, for progress events.
+    bytes_downloaded: Arc<AtomicU64>,
+}
 
-            pb.finish_with_message(format!("{} - Done", path_str));
+/// Downloads a file, returning its size and checksum. A failed download leaves no partial file behind.
+async fn download_file(
+    url: &str,
+    output_file: PathBuf,
+    ctx: &DownloadContext,
+) -> Result<(u64, String), MyError> {
+    progress::stats().add_request();
+    let response = ctx.client.get(url).send().await?.error_for_status()?;
+    let total_size = response.content_length().unwrap_or(0);
+    let pb = ctx.multi_progress.add(ProgressBar::new(total_size));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta}) {msg}")
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+    let path_str = tile_file_name(url)?.to_string();
+    pb.set_message(path_str.clone());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    // Download next to the target and rename once complete, so an interrupted
+    // download neither looks cached nor clobbers an existing copy.
+    let mut part_path = output_file.clone().into_os_string();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
+    let result = write_part_file(response, &part_path, &pb, ctx).await;
+    pb.finish_and_clear();
+    match result {
+        Ok(checksum) => {
+            fs::rename(&part_path, &output_file).await?;
+            debug!("{} - Done", path_str);
+            Ok(checksum)
         }
         Err(e) => {
-            eprintln!("Error downloading {}: {}", url, e);
+            let _ = fs::remove_file(&part_path).await;
+            Err(e)
+        }
+    }
+}
+
+/// Streams a response into `part_path`, returning its size and checksum.
+async fn write_part_file(
+    response: reqwest::Response,
+    part_path: &Path,
+    pb: &ProgressBar,
+    ctx: &DownloadContext,
+) -> Result<(u64, String), MyError> {
+    let mut file = File::create(part_path).await?;
+    let mut stream = response.bytes_stream();
+    let mut checksum = ChecksumWriter::default();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if let Some(rate_limiter) = &ctx.rate_limiter {
+            rate_limiter.consume(chunk.len() as u64).await;
         }
+        file.write_all(&chunk).await?;
+        checksum.update(&chunk);
+        pb.inc(chunk.len() as u64);
+        progress::stats().add_bytes(chunk.len() as u64);
+        ctx.bytes_downloaded
+            .fetch_add(chunk.len() as u64, Ordering::Relaxed);
     }
+    file.flush().await?;
+    Ok(checksum.finish())
 }
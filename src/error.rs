@@ -13,13 +13,139 @@ pub enum MyError {
     StacIOError(#[from] stac_io::Error),
     #[error("STAC error: {0}")]
     SendError(#[from] Box<tokio::sync::mpsc::error::SendError<stac::Item>>),
+    #[error("Cancelled.")]
+    Cancelled,
+    #[error("Timed out after {1:?} fetching {0}")]
+    Timeout(String, std::time::Duration),
+    #[error("{collections} collections and {items} items could not be read, the results are incomplete.")]
+    IncompleteSearch { collections: usize, items: usize },
+    #[error("{failed} of {checked} cached tiles are missing or changed since download.")]
+    CacheMismatch { failed: usize, checked: usize },
     #[error("Join error: {0}")]
     JoinError(#[from] tokio::task::JoinError),
     #[error("Error when setting thread permit limit.{0}")]
     ThreadPermitError(&'static str),
+    #[error("Concurrency limit error: {0}")]
+    SemaphoreError(#[from] tokio::sync::AcquireError),
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("Invalid search request: {0}")]
+    InvalidRequest(String),
+    #[error("Cannot name a local file after {0}")]
+    InvalidHref(String),
+    #[error("Could not download {url}")]
+    DownloadError {
+        url: String,
+        #[source]
+        source: Box<MyError>,
+    },
+    #[error("{failed} of {total} downloads failed")]
+    DownloadsFailed {
+        failed: usize,
+        total: usize,
+        #[source]
+        source: Box<MyError>,
+    },
+    #[error("Expected download size {size} exceeds the limit of {limit}, aborting.")]
+    DownloadTooLarge { size: String, limit: String },
+    #[error(
+        "Not enough free disk space in {folder}: {needed} needed, {available} available, aborting."
+    )]
+    InsufficientSpace {
+        folder: String,
+        needed: String,
+        available: String,
+    },
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("IO error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Cannot open the interactive table: {0}")]
+    TerminalError(#[source] std::io::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("CSV error: {0}")]
+    CsvError(#[from] csv::Error),
+    #[error("Could not read the config file: {0}")]
+    ConfigError(#[from] toml::de::Error),
+    #[error("Could not write the config file: {0}")]
+    ConfigWriteError(#[from] toml::ser::Error),
+    #[error("Unsupported export format for {0}, use a .geojson, .fgb, .kml or .parquet file.")]
+    UnsupportedExportFormat(String),
+    #[error("FlatGeobuf error: {0}")]
+    FlatGeobufError(#[from] flatgeobuf::Error),
+    #[error("Geometry error: {0}")]
+    GeozeroError(#[from] geozero::error::GeozeroError),
+    #[error("GeoPackage error: {0}")]
+    SqliteError(#[from] rusqlite::Error),
+}
+
+/// Broad kinds of errors. Each maps to the exit code of the command line tool, so scripts can
+/// tell them apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ErrorKind {
+    /// A bug or an unexpected failure of the runtime.
+    Internal = 1,
+    /// Invalid arguments or selections. Argument errors caught by the parser exit with 2 as well.
+    Usage = 2,
+    /// The configuration file could not be read or written.
+    Config = 3,
+    /// A request failed or timed out.
+    Network = 4,
+    /// A local file could not be read or written.
+    Io = 5,
+    /// A response or file could not be parsed.
+    Parse = 6,
+    /// The search finished, but some collections or items could not be read (`--strict`), or
+    /// cached tiles are missing or changed.
+    Incomplete = 7,
+    /// Cancelled with Ctrl-C.
+    Cancelled = 130,
 }
 
 impl MyError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MyError::NoFilterProvided
+            | MyError::DimensionAndCoordinateRange
+            | MyError::ThreadPermitError(_)
+            | MyError::InvalidInput(_)
+            | MyError::InvalidRequest(_)
+            | MyError::UnsupportedExportFormat(_)
+            | MyError::DownloadTooLarge { .. } => ErrorKind::Usage,
+            MyError::ConfigError(_) | MyError::ConfigWriteError(_) => ErrorKind::Config,
+            MyError::StacIOError(_) | MyError::HttpError(_) | MyError::Timeout(..) => {
+                ErrorKind::Network
+            }
+            MyError::IoError(_)
+            | MyError::TerminalError(_)
+            | MyError::CsvError(_)
+            | MyError::FlatGeobufError(_)
+            | MyError::SqliteError(_)
+            | MyError::InsufficientSpace { .. } => ErrorKind::Io,
+            MyError::StacError(_)
+            | MyError::JsonError(_)
+            | MyError::GeozeroError(_)
+            | MyError::InvalidHref(_) => ErrorKind::Parse,
+            MyError::IncompleteSearch { .. } | MyError::CacheMismatch { .. } => {
+                ErrorKind::Incomplete
+            }
+            MyError::Cancelled => ErrorKind::Cancelled,
+            MyError::DownloadError { source, .. } | MyError::DownloadsFailed { source, .. } => {
+                source.kind()
+            }
+            MyError::SendError(_) | MyError::JoinError(_) | MyError::SemaphoreError(_) => {
+                ErrorKind::Internal
+            }
+        }
+    }
+
+    /// Exit code of the command line tool for this error.
+    pub fn exit_code(&self) -> i32 {
+        self.kind() as i32
+    }
+
     /// Recursively formats the error and its sources into a readable string.
     pub fn report(&self) {
         let mut err: &dyn error::Error = self;
@@ -31,3 +157,31 @@ impl MyError {
         ::log::error!("Error: {}", s);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(MyError::NoFilterProvided.exit_code(), 2);
+        assert_eq!(MyError::Cancelled.exit_code(), 130);
+        let mismatch = MyError::CacheMismatch {
+            failed: 1,
+            checked: 4,
+        };
+        assert_eq!(mismatch.exit_code(), 7);
+        let io = || MyError::from(std::io::Error::other("disk full"));
+        assert_eq!(io().exit_code(), 5);
+        // Download failures exit with the code of their cause
+        let error = MyError::DownloadsFailed {
+            failed: 1,
+            total: 2,
+            source: Box::new(MyError::DownloadError {
+                url: "https://example.com/a.tif".to_string(),
+                source: Box::new(io()),
+            }),
+        };
+        assert_eq!(error.kind(), ErrorKind::Io);
+    }
+}
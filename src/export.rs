@@ -0,0 +1,457 @@
+use flatgeobuf::{ColumnType, FgbWriter, GeometryType};
+use geozero::geojson::GeoJsonString;
+use geozero::{ColumnValue, PropertyProcessor};
+use serde_json::{json, Map, Value};
+use stac::{Collection, Item, Link, Links, SelfHref};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::DatasetMatch;
+use crate::linz_s3_filter::utils::{absolute_href, collection_date_range, extract_value_before_m};
+
+/// Vector formats footprints can be exported to, chosen by file extension.
+/// stac-geoparquet holds whole items rather than footprints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    GeoJson,
+    FlatGeobuf,
+    Kml,
+    GeoParquet,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "geojson" | "json" => Some(ExportFormat::GeoJson),
+            "fgb" => Some(ExportFormat::FlatGeobuf),
+            "kml" => Some(ExportFormat::Kml),
+            "parquet" | "geoparquet" => Some(ExportFormat::GeoParquet),
+            _ => None,
+        }
+    }
+}
+
+/// The outline of a collection or item, with the attributes written to every format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Footprint {
+    /// "collection" or "item".
+    pub kind: &'static str,
+    pub id: String,
+    /// Title of the collection.
+    pub title: String,
+    /// Date or date range, e.g. "2021-03-01/2021-09-30".
+    pub date: Option<String>,
+    /// Resolution in metres, parsed from the collection title.
+    pub resolution: Option<f64>,
+    /// Asset URL for items, collection JSON URL for collections.
+    pub url: Option<String>,
+    /// GeoJSON geometry.
+    pub geometry: Value,
+}
+
+/// Columns of the exported attributes, in the order of `Footprint::properties`.
+const COLUMNS: [(&str, ColumnType); 6] = [
+    ("kind", ColumnType::String),
+    ("id", ColumnType::String),
+    ("title", ColumnType::String),
+    ("date", ColumnType::String),
+    ("resolution", ColumnType::Double),
+    ("url", ColumnType::String),
+];
+
+impl Footprint {
+    /// Footprint of a collection from the first box of its spatial extent, `None` if it has none.
+    pub fn from_collection(collection: &Collection) -> Option<Self> {
+        let bbox = collection.extent.spatial.bbox.first()?;
+        let title = collection
+            .title
+            .clone()
+            .unwrap_or_else(|| collection.id.clone());
+        let (start, end) = collection_date_range(collection);
+        let date = |date: Option<chrono::DateTime<chrono::Utc>>| {
+            date.map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|| "..".to_string())
+        };
+        Some(Footprint {
+            kind: "collection",
+            id: collection.id.clone(),
+            resolution: resolution(&title),
+            title,
+            date: Some(format!("{}/{}", date(start), date(end))),
+            url: collection
+                .links()
+                .iter()
+                .find(|link| link.is_self())
+                .map(|link| link.href.to_string()),
+            geometry: bbox_polygon([bbox.xmin(), bbox.ymin(), bbox.xmax(), bbox.ymax()]),
+        })
+    }
+
+    /// Footprints of the items of a matched dataset, from the item geometry or else its bbox.
+    pub fn from_dataset(dataset: &DatasetMatch) -> Vec<Self> {
+        let resolution = resolution(&dataset.title);
+        dataset
+            .items
+            .iter()
+            .filter_map(|item| {
+                let geometry = item_geometry(item)?;
+                Some(Footprint {
+                    kind: "item",
+                    id: item.id.clone(),
+                    title: dataset.title.clone(),
+                    date: item_date(item),
+                    resolution,
+                    url: dataset
+                        .tiles
+                        .iter()
+                        .find(|tile| tile.item_id == item.id)
+                        .map(|tile| tile.href.clone()),
+                    geometry,
+                })
+            })
+            .collect()
+    }
+
+    /// Attributes in the order of `COLUMNS`, unknown values as `None`.
+    fn properties(&self) -> [(&'static str, Option<Value>); 6] {
+        [
+            ("kind", Some(self.kind.into())),
+            ("id", Some(self.id.clone().into())),
+            ("title", Some(self.title.clone().into())),
+            ("date", self.date.clone().map(Value::from)),
+            ("resolution", self.resolution.map(Value::from)),
+            ("url", self.url.clone().map(Value::from)),
+        ]
+    }
+
+    pub fn to_geojson(&self) -> Value {
+        let properties: Map<String, Value> = self
+            .properties()
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.unwrap_or(Value::Null)))
+            .collect();
+        json!({
+            "type": "Feature",
+            "properties": properties,
+            "geometry": self.geometry,
+        })
+    }
+}
+
+/// GeoJSON geometry of an item, from its geometry or else its bbox.
+pub(crate) fn item_geometry(item: &Item) -> Option<Value> {
+    match &item.geometry {
+        Some(geometry) => serde_json::to_value(geometry).ok(),
+        None => {
+            let bbox = item.bbox.as_ref()?;
+            Some(bbox_polygon([
+                bbox.xmin(),
+                bbox.ymin(),
+                bbox.xmax(),
+                bbox.ymax(),
+            ]))
+        }
+    }
+}
+
+/// Date of an item, or its date range if it has a start and end.
+pub(crate) fn item_date(item: &Item) -> Option<String> {
+    let properties = &item.properties;
+    match (properties.start_datetime, properties.end_datetime) {
+        (Some(start), Some(end)) => Some(format!(
+            "{}/{}",
+            start.format("%Y-%m-%d"),
+            end.format("%Y-%m-%d")
+        )),
+        _ => properties
+            .datetime
+            .map(|datetime| datetime.format("%Y-%m-%d").to_string()),
+    }
+}
+
+fn resolution(title: &str) -> Option<f64> {
+    Some(extract_value_before_m(title)).filter(|resolution| *resolution != f64::MAX)
+}
+
+fn bbox_polygon([xmin, ymin, xmax, ymax]: [f64; 4]) -> Value {
+    json!({
+        "type": "Polygon",
+        "coordinates": [[[xmin, ymin], [xmax, ymin], [xmax, ymax], [xmin, ymax], [xmin, ymin]]],
+    })
+}
+
+/// Writes footprints to `path`, in the format given by its extension.
+pub fn write_footprints(path: &Path, footprints: &[Footprint]) -> Result<(), MyError> {
+    match ExportFormat::from_path(path) {
+        Some(ExportFormat::GeoJson) => {
+            let features: Vec<Value> = footprints.iter().map(Footprint::to_geojson).collect();
+            let collection = json!({"type": "FeatureCollection", "features": features});
+            std::fs::write(path, serde_json::to_vec_pretty(&collection)?)?;
+        }
+        Some(ExportFormat::FlatGeobuf) => write_flatgeobuf(path, footprints)?,
+        Some(ExportFormat::Kml) => std::fs::write(path, to_kml(footprints))?,
+        Some(ExportFormat::GeoParquet) | None => {
+            return Err(MyError::UnsupportedExportFormat(path.display().to_string()))
+        }
+    }
+    Ok(())
+}
+
+/// Writes the selected datasets to `path`: all their items for stac-geoparquet, or else the item footprints.
+/// Returns the number of features written.
+pub fn write_datasets(path: &Path, datasets: &[&DatasetMatch]) -> Result<usize, MyError> {
+    if ExportFormat::from_path(path) == Some(ExportFormat::GeoParquet) {
+        let items: Vec<Item> = datasets
+            .iter()
+            .flat_map(|dataset| dataset.items.iter().cloned())
+            .collect();
+        let count = items.len();
+        write_geoparquet(path, items)?;
+        Ok(count)
+    } else {
+        let footprints: Vec<Footprint> = datasets
+            .iter()
+            .flat_map(|dataset| Footprint::from_dataset(dataset))
+            .collect();
+        write_footprints(path, &footprints)?;
+        Ok(footprints.len())
+    }
+}
+
+/// Writes items to a stac-geoparquet file, made usable without the catalog they came from.
+pub fn write_geoparquet(path: &Path, items: Vec<Item>) -> Result<(), MyError> {
+    let items: Vec<Item> = items.into_iter().map(self_contained).collect();
+    stac::geoparquet::into_writer(BufWriter::new(File::create(path)?), items)?;
+    Ok(())
+}
+
+/// Makes the asset hrefs of an item absolute and gives it an absolute self link.
+fn self_contained(mut item: Item) -> Item {
+    let Some(self_href) = item.self_href().map(|href| href.to_string()) else {
+        return item;
+    };
+    let hrefs: Vec<String> = item
+        .assets
+        .values()
+        .map(|asset| absolute_href(&item, &asset.href.to_string()))
+        .collect();
+    for (asset, href) in item.assets.values_mut().zip(hrefs) {
+        asset.href = href;
+    }
+    item.links.retain(|link| !link.is_self());
+    item.links.push(Link::new(self_href, "self"));
+    item
+}
+
+fn write_flatgeobuf(path: &Path, footprints: &[Footprint]) -> Result<(), MyError> {
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("footprints");
+    let mut fgb = FgbWriter::create(name, GeometryType::Unknown)?;
+    for (column, column_type) in COLUMNS {
+        fgb.add_column(column, column_type, |_, _| {});
+    }
+    for footprint in footprints {
+        let geometry = GeoJsonString(footprint.geometry.to_string());
+        let mut result = Ok(());
+        fgb.add_feature_geom(geometry, |feature| {
+            for (i, (column, value)) in footprint.properties().into_iter().enumerate() {
+                let value = match &value {
+                    Some(Value::String(s)) => ColumnValue::String(s),
+                    Some(Value::Number(n)) => ColumnValue::Double(n.as_f64().unwrap_or(f64::NAN)),
+                    _ => continue,
+                };
+                if let Err(e) = feature.property(i, column, &value) {
+                    result = Err(e);
+                }
+            }
+        })?;
+        result?;
+    }
+    fgb.write(BufWriter::new(File::create(path)?))?;
+    Ok(())
+}
+
+fn to_kml(footprints: &[Footprint]) -> String {
+    let mut kml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n",
+    );
+    for footprint in footprints {
+        let _ = writeln!(kml, "<Placemark>");
+        let _ = writeln!(
+            kml,
+            "<name>{}</name>",
+            escape_xml(&format!("{} {}", footprint.title, footprint.id))
+        );
+        let _ = writeln!(kml, "<ExtendedData>");
+        for (column, value) in footprint.properties() {
+            let value = match value {
+                Some(Value::String(s)) => s,
+                Some(value) => value.to_string(),
+                None => continue,
+            };
+            let _ = writeln!(
+                kml,
+                "<Data name=\"{}\"><value>{}</value></Data>",
+                column,
+                escape_xml(&value)
+            );
+        }
+        let _ = writeln!(kml, "</ExtendedData>");
+        kml_geometry(&mut kml, &footprint.geometry);
+        let _ = writeln!(kml, "</Placemark>");
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    kml
+}
+
+/// Appends the KML form of a GeoJSON Polygon or MultiPolygon. Other geometry types are left out.
+fn kml_geometry(kml: &mut String, geometry: &Value) {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str() {
+        Some("Polygon") => kml_polygon(kml, coordinates),
+        Some("MultiPolygon") => {
+            let _ = writeln!(kml, "<MultiGeometry>");
+            for polygon in coordinates.as_array().into_iter().flatten() {
+                kml_polygon(kml, polygon);
+            }
+            let _ = writeln!(kml, "</MultiGeometry>");
+        }
+        _ => {}
+    }
+}
+
+fn kml_polygon(kml: &mut String, rings: &Value) {
+    let _ = writeln!(kml, "<Polygon>");
+    for (i, ring) in rings.as_array().into_iter().flatten().enumerate() {
+        let boundary = if i == 0 {
+            "outerBoundaryIs"
+        } else {
+            "innerBoundaryIs"
+        };
+        let points: Vec<String> = ring
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|point| Some(format!("{},{}", point[0].as_f64()?, point[1].as_f64()?)))
+            .collect();
+        let _ = writeln!(
+            kml,
+            "<{0}><LinearRing><coordinates>{1}</coordinates></LinearRing></{0}>",
+            boundary,
+            points.join(" ")
+        );
+    }
+    let _ = writeln!(kml, "</Polygon>");
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_self_contained() {
+        let mut item = Item::new("an-id");
+        item.assets
+            .insert("data".to_string(), stac::Asset::new("./an-id.tiff"));
+        item.links = vec![Link::new("./an-id.json", "self")];
+        *item.self_href_mut() = Some("https://example.com/dem/an-id.json".into());
+        let item = self_contained(item);
+        assert_eq!(
+            item.assets["data"].href.to_string(),
+            "https://example.com/dem/an-id.tiff"
+        );
+        let self_links: Vec<_> = item.links.iter().filter(|link| link.is_self()).collect();
+        assert_eq!(self_links.len(), 1);
+        assert_eq!(
+            self_links[0].href.to_string(),
+            "https://example.com/dem/an-id.json"
+        );
+    }
+
+    fn footprint() -> Footprint {
+        Footprint {
+            kind: "item",
+            id: "CC11_1000_0101".to_string(),
+            title: "Otago LiDAR 1m DEM (2021) & more".to_string(),
+            date: Some("2021-03-01".to_string()),
+            resolution: Some(1.0),
+            url: None,
+            geometry: bbox_polygon([170.0, -46.0, 170.5, -45.5]),
+        }
+    }
+
+    #[test]
+    fn test_export_format_from_path() {
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a.GeoJSON")),
+            Some(ExportFormat::GeoJson)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a.fgb")),
+            Some(ExportFormat::FlatGeobuf)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a.kml")),
+            Some(ExportFormat::Kml)
+        );
+        assert_eq!(
+            ExportFormat::from_path(Path::new("a.parquet")),
+            Some(ExportFormat::GeoParquet)
+        );
+        assert_eq!(ExportFormat::from_path(Path::new("a.shp")), None);
+    }
+
+    #[test]
+    fn test_collection_footprint() {
+        let mut collection = Collection::new("an-id", "a description");
+        assert_eq!(Footprint::from_collection(&collection), None);
+        collection.title = Some("Otago 0.5m Urban Aerial Photos".to_string());
+        collection.extent.spatial.bbox = vec![stac::Bbox::new(168.5, -46.5, 171.0, -44.0)];
+        let footprint = Footprint::from_collection(&collection).unwrap();
+        assert_eq!(footprint.resolution, Some(0.5));
+        assert_eq!(footprint.date.as_deref(), Some("../.."));
+        assert_eq!(
+            footprint.geometry["coordinates"][0][2],
+            json!([171.0, -44.0])
+        );
+    }
+
+    #[test]
+    fn test_write_geojson_and_kml() {
+        let dir = tempdir().unwrap();
+        let geojson = dir.path().join("footprints.geojson");
+        write_footprints(&geojson, &[footprint()]).unwrap();
+        let written: Value = serde_json::from_slice(&std::fs::read(&geojson).unwrap()).unwrap();
+        assert_eq!(written["features"][0]["properties"]["resolution"], 1.0);
+        assert_eq!(written["features"][0]["properties"]["url"], Value::Null);
+        assert_eq!(written["features"][0]["geometry"]["type"], "Polygon");
+
+        let kml = dir.path().join("footprints.kml");
+        write_footprints(&kml, &[footprint()]).unwrap();
+        let written = std::fs::read_to_string(&kml).unwrap();
+        assert!(
+            written.contains("<name>Otago LiDAR 1m DEM (2021) &amp; more CC11_1000_0101</name>")
+        );
+        assert!(written.contains("<Data name=\"resolution\"><value>1.0</value></Data>"));
+        assert!(!written.contains("name=\"url\""));
+        assert!(written.contains(
+            "<coordinates>170,-46 170.5,-46 170.5,-45.5 170,-45.5 170,-46</coordinates>"
+        ));
+
+        assert!(write_footprints(&dir.path().join("footprints.shp"), &[]).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use futures::{stream, StreamExt};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use stac::{Collection, Item};
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use crate::error::MyError;
+use crate::linz_s3_filter::linz_bucket::LinzBucket;
+use crate::linz_s3_filter::utils::item_count;
+
+/// Metadata of a collection, as printed by the `info` command.
+#[derive(Debug, Serialize)]
+pub struct CollectionInfo {
+    pub id: String,
+    pub title: Option<String>,
+    pub description: String,
+    pub license: String,
+    pub providers: Vec<Value>,
+    pub extent: Value,
+    pub summaries: Option<Map<String, Value>>,
+    pub stac_extensions: Vec<String>,
+    /// Fields added by STAC extensions or the publisher, such as `linz:region`.
+    pub extension_fields: Map<String, Value>,
+    pub item_count: usize,
+    /// Statistics of the item assets, `None` if the items were not fetched.
+    pub assets: Option<AssetStats>,
+}
+
+/// Totals over the assets of a collection's items.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct AssetStats {
+    pub items_read: usize,
+    pub asset_count: usize,
+    /// Total of the `file:size` of the assets that have one.
+    pub total_bytes: u64,
+    pub unknown_size: usize,
+    /// Distinct coordinate reference systems, from `proj:code` or `proj:epsg`.
+    pub crs: BTreeSet<String>,
+    /// Distinct ground sample distances in metres, from `gsd`.
+    pub resolutions: Vec<f64>,
+}
+
+impl AssetStats {
+    pub fn new(items: &[Item]) -> Self {
+        let mut stats = AssetStats {
+            items_read: items.len(),
+            ..Default::default()
+        };
+        for item in items {
+            let properties = &item.properties.additional_fields;
+            for asset in item.assets.values() {
+                let fields = &asset.additional_fields;
+                stats.asset_count += 1;
+                match fields.get("file:size").and_then(Value::as_u64) {
+                    Some(size) => stats.total_bytes += size,
+                    None => stats.unknown_size += 1,
+                }
+                if let Some(crs) = crs(fields).or_else(|| crs(properties)) {
+                    stats.crs.insert(crs);
+                }
+                let gsd = fields.get("gsd").or_else(|| properties.get("gsd"));
+                if let Some(gsd) = gsd.and_then(Value::as_f64) {
+                    if !stats.resolutions.contains(&gsd) {
+                        stats.resolutions.push(gsd);
+                    }
+                }
+            }
+        }
+        stats.resolutions.sort_by(|a, b| a.total_cmp(b));
+        stats
+    }
+}
+
+fn crs(fields: &Map<String, Value>) -> Option<String> {
+    match fields.get("proj:code") {
+        Some(Value::String(code)) => Some(code.clone()),
+        _ => fields
+            .get("proj:epsg")
+            .and_then(Value::as_u64)
+            .map(|epsg| format!("EPSG:{}", epsg)),
+    }
+}
+
+impl CollectionInfo {
+    pub fn new(collection: &Collection, assets: Option<AssetStats>) -> Result<Self, MyError> {
+        let Value::Object(mut fields) = serde_json::to_value(collection)? else {
+            unreachable!("collections serialize to objects");
+        };
+        let mut take = |key: &str| fields.remove(key).unwrap_or(Value::Null);
+        let providers = match take("providers") {
+            Value::Array(providers) => providers,
+            _ => vec![],
+        };
+        let extent = take("extent");
+        let stac_extensions = match take("stac_extensions") {
+            Value::Array(extensions) => extensions
+                .iter()
+                .filter_map(|extension| extension.as_str().map(str::to_string))
+                .collect(),
+            _ => vec![],
+        };
+        let extension_fields = fields
+            .into_iter()
+            .filter(|(key, _)| key.contains(':'))
+            .collect();
+        Ok(CollectionInfo {
+            id: collection.id.clone(),
+            title: collection.title.clone(),
+            description: collection.description.clone(),
+            license: collection.license.clone(),
+            providers,
+            extent,
+            summaries: collection.summaries.clone(),
+            stac_extensions,
+            extension_fields,
+            item_count: item_count(collection),
+            assets,
+        })
+    }
+}
+
+impl Display for CollectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Id: {}", self.id)?;
+        writeln!(f, "Title: {}", self.title.as_deref().unwrap_or("N/A"))?;
+        writeln!(f, "License: {}", self.license)?;
+        writeln!(f, "Description: {}", self.description)?;
+        for provider in &self.providers {
+            let name = provider.get("name").and_then(Value::as_str).unwrap_or("");
+            let roles: Vec<_> = provider
+                .get("roles")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect();
+            writeln!(f, "Provider: {} ({})", name, roles.join(", "))?;
+        }
+        if let Some(bboxes) = self
+            .extent
+            .pointer("/spatial/bbox")
+            .and_then(Value::as_array)
+        {
+            for bbox in bboxes {
+                writeln!(f, "Spatial extent: {}", bbox)?;
+            }
+        }
+        if let Some(intervals) = self
+            .extent
+            .pointer("/temporal/interval")
+            .and_then(Value::as_array)
+        {
+            for interval in intervals {
+                let date = |i: usize| interval.get(i).and_then(Value::as_str).unwrap_or("..");
+                writeln!(f, "Temporal extent: {}/{}", date(0), date(1))?;
+            }
+        }
+        for (key, value) in self.summaries.iter().flatten() {
+            writeln!(f, "Summary {}: {}", key, value)?;
+        }
+        for extension in &self.stac_extensions {
+            writeln!(f, "Extension: {}", extension)?;
+        }
+        for (key, value) in &self.extension_fields {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        writeln!(f, "Items: {}", self.item_count)?;
+        if let Some(assets) = &self.assets {
+            write!(
+                f,
+                "Assets: {} in {} items, {}",
+                assets.asset_count,
+                assets.items_read,
+                HumanBytes(assets.total_bytes)
+            )?;
+            if assets.unknown_size > 0 {
+                write!(f, " ({} of unknown size)", assets.unknown_size)?;
+            }
+            writeln!(f)?;
+            let crs: Vec<_> = assets.crs.iter().map(String::as_str).collect();
+            writeln!(f, "CRS: {}", crs.join(", "))?;
+            let resolutions: Vec<_> = assets
+                .resolutions
+                .iter()
+                .map(|gsd| format!("{}m", gsd))
+                .collect();
+            writeln!(f, "Resolution: {}", resolutions.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetches every item of a collection, `concurrency` at a time. Items that cannot be read are added to the
+/// failures of the bucket and skipped.
+pub async fn fetch_items(
+    linz_bucket: &LinzBucket,
+    collection: &Collection,
+    concurrency: usize,
+) -> Vec<Item> {
+    let hrefs: Vec<String> = collection
+        .links
+        .iter()
+        .filter(|link| link.is_item())
+        .map(|link| link.href.to_string())
+        .collect();
+    stream::iter(hrefs)
+        .map(|href| async move {
+            linz_bucket
+                .fetch_policy
+                .get::<Item>(&linz_bucket.store, &href)
+                .await
+                .map_err(|e| linz_bucket.failures.add_item(&href, &e))
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|result| async move { result.ok() })
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stac::Asset;
+
+    fn item(assets: &[(Option<u64>, Value)]) -> Item {
+        let mut item = Item::new("an-id");
+        item.properties
+            .additional_fields
+            .insert("proj:epsg".to_string(), 2193.into());
+        for (i, (size, gsd)) in assets.iter().enumerate() {
+            let mut asset = Asset::new(format!("./{}.tif", i));
+            if let Some(size) = size {
+                asset
+                    .additional_fields
+                    .insert("file:size".to_string(), (*size).into());
+            }
+            asset
+                .additional_fields
+                .insert("gsd".to_string(), gsd.clone());
+            item.assets.insert(i.to_string(), asset);
+        }
+        item
+    }
+
+    #[test]
+    fn test_asset_stats() {
+        let mut other_crs = item(&[(Some(5), 0.5.into())]);
+        other_crs
+            .properties
+            .additional_fields
+            .insert("proj:code".to_string(), "EPSG:3857".into());
+        let items = vec![
+            item(&[(Some(10), 1.into()), (None, Value::Null)]),
+            item(&[(Some(20), 1.into())]),
+            other_crs,
+        ];
+        let stats = AssetStats::new(&items);
+        assert_eq!(stats.items_read, 3);
+        assert_eq!(stats.asset_count, 4);
+        assert_eq!(stats.total_bytes, 35);
+        assert_eq!(stats.unknown_size, 1);
+        assert_eq!(
+            stats.crs.into_iter().collect::<Vec<_>>(),
+            ["EPSG:2193", "EPSG:3857"]
+        );
+        assert_eq!(stats.resolutions, [0.5, 1.0]);
+    }
+
+    #[test]
+    fn test_collection_info() {
+        let mut collection = Collection::new("an-id", "a description");
+        collection
+            .additional_fields
+            .insert("linz:region".to_string(), "otago".into());
+        collection.links = vec![stac::Link::new("./a.json", "item")];
+        let info = CollectionInfo::new(&collection, None).unwrap();
+        assert_eq!(info.item_count, 1);
+        assert_eq!(info.extension_fields.len(), 1);
+        assert!(!info.extension_fields.contains_key("extent"));
+
+        let text = info.to_string();
+        assert!(text.contains("Id: an-id\n"));
+        assert!(text.contains("linz:region: \"otago\"\n"));
+        assert!(!text.contains("Assets:"));
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["extension_fields"]["linz:region"], "otago");
+    }
+}
@@ -1,9 +1,25 @@
 pub mod args;
+pub mod cache;
+pub mod changes;
 pub mod download;
 pub mod error;
+pub mod export;
+pub mod info;
 pub mod linz_s3_filter;
+pub mod manifest;
+pub mod progress;
+pub mod rate_limit;
 pub mod search;
+pub mod selection;
+pub mod sync;
+pub mod tile_index;
+pub mod tui;
 
 pub use args::{Cli, SpatialFilter};
 pub use download::process_tile_list;
-pub use search::search_catalog;
+pub use search::{
+    collect_datasets, Area, DownloadSettings, IndexCache, SearchOutput, SearchRequest, SearchSource,
+};
+#[allow(deprecated)]
+pub use search::{search_catalog, search_stream};
+pub use tokio_util::sync::CancellationToken;
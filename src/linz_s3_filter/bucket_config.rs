@@ -1,14 +1,38 @@
-// use log::debug;
-use serde::Deserialize;
-// use std::fs;
-// use std::io::ErrorKind;
+use clap::ValueEnum;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::sync::OnceLock;
-// use std::time::Duration;
+use std::time::Duration;
 
-#[derive(Debug, Deserialize)]
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::BucketName;
+use crate::linz_s3_filter::fetch::FetchPolicy;
+
+/// Name of the configuration file, read from the current directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ConfigFile {
-    skip_signature: &'static str,
-    region: &'static str,
+    pub skip_signature: String,
+    pub region: String,
+    /// STAC API URLs by bucket name, e.g. `elevation = "https://..."`. Buckets listed here are
+    /// searched through the API instead of by crawling their static catalog.
+    pub stac_api: BTreeMap<String, String>,
+    /// stac-geoparquet files by bucket name. Items of the collections in these files are read
+    /// from them instead of being fetched one by one.
+    pub geoparquet: BTreeMap<String, String>,
+    /// Directory the item indexes of crawled collections are kept in, by default `linz_s3/index`
+    /// in the user's cache directory. Empty to disable indexing.
+    pub index_dir: String,
+    /// Seconds a request for a collection or item may take before it is given up.
+    pub timeout_secs: u64,
+    /// How often a failed request for a collection or item is tried again.
+    pub retries: u32,
 }
 
 static CONFIG_INSTANCE: OnceLock<ConfigFile> = OnceLock::new();
@@ -17,37 +41,140 @@ impl ConfigFile {
         CONFIG_INSTANCE.get().expect("Config is not initialized")
     }
 
-    pub fn init() {
-        let config = ConfigFile::default();
+    /// Loads the configuration file, falling back to the defaults if there is none.
+    pub fn init() -> Result<(), MyError> {
+        let config = Self::load()?;
         CONFIG_INSTANCE
             .set(config)
             .expect("Config already initialized");
+        Ok(())
+    }
+
+    /// Reads the configuration file, or the defaults if there is none, without making it the
+    /// global configuration.
+    pub fn load() -> Result<ConfigFile, MyError> {
+        load_config(Path::new(CONFIG_FILE))
+    }
+
+    pub fn to_toml(&self) -> Result<String, MyError> {
+        Ok(toml::to_string_pretty(self)?)
     }
 }
 impl Default for ConfigFile {
     fn default() -> Self {
         ConfigFile {
-            skip_signature: "true",
-            region: "ap-southeast-2",
+            skip_signature: "true".to_string(),
+            region: "ap-southeast-2".to_string(),
+            stac_api: BTreeMap::new(),
+            geoparquet: BTreeMap::new(),
+            index_dir: default_index_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            timeout_secs: 30,
+            retries: 3,
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Result<ConfigFile, MyError> {
+    match fs::read_to_string(path) {
+        Ok(config_str) => Ok(toml::from_str(&config_str)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            debug!("Config file not found, using default configuration.");
+            Ok(ConfigFile::default())
         }
+        Err(e) => Err(e.into()),
     }
 }
 
-// fn load_config() -> ConfigFile {
-//     match fs::read_to_string("config.toml") {
-//         Ok(config_str) => toml::from_str(&config_str).expect("Failed to parse config file"),
-//         Err(e) if e.kind() == ErrorKind::NotFound => {
-//             debug!("Config file not found, using default configuration.");
-//             ConfigFile::default()
-//         }
-//         Err(e) => panic!("Failed to read config file: {:?}", e),
-//     }
-// }
-pub fn get_opts() -> Vec<(&'static str, &'static str)> {
+pub fn get_opts() -> Vec<(&'static str, String)> {
     let config = CONFIG_INSTANCE.get_or_init(ConfigFile::default);
 
     vec![
-        ("skip_signature", config.skip_signature),
-        ("region", config.region),
+        ("skip_signature", config.skip_signature.clone()),
+        ("region", config.region.clone()),
     ]
 }
+
+fn bucket_setting(settings: &BTreeMap<String, String>, bucket: BucketName) -> Option<String> {
+    let name = bucket.to_possible_value()?;
+    settings.get(name.get_name()).cloned()
+}
+
+/// URL of the STAC API configured for a bucket, if any.
+pub fn stac_api_url(bucket: BucketName) -> Option<String> {
+    let config = CONFIG_INSTANCE.get_or_init(ConfigFile::default);
+    bucket_setting(&config.stac_api, bucket)
+}
+
+/// stac-geoparquet file configured for a bucket, if any.
+pub fn geoparquet_path(bucket: BucketName) -> Option<PathBuf> {
+    let config = CONFIG_INSTANCE.get_or_init(ConfigFile::default);
+    bucket_setting(&config.geoparquet, bucket).map(PathBuf::from)
+}
+
+/// The user's cache directory: `$XDG_CACHE_HOME` or `~/.cache` on Linux, `~/Library/Caches`
+/// on macOS and `%LOCALAPPDATA%` on Windows.
+fn cache_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if cfg!(windows) {
+        return var("LOCALAPPDATA").map(PathBuf::from);
+    }
+    let home = var("HOME").map(PathBuf::from);
+    if cfg!(target_os = "macos") {
+        return home.map(|home| home.join("Library").join("Caches"));
+    }
+    var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.map(|home| home.join(".cache")))
+}
+
+/// Where item indexes are kept unless `index_dir` is set, `None` without a cache directory.
+fn default_index_dir() -> Option<PathBuf> {
+    Some(cache_dir()?.join("linz_s3").join("index"))
+}
+
+/// Directory for item indexes, `None` if indexing is disabled.
+pub fn index_dir() -> Option<PathBuf> {
+    let config = CONFIG_INSTANCE.get_or_init(ConfigFile::default);
+    Some(PathBuf::from(&config.index_dir)).filter(|dir| !dir.as_os_str().is_empty())
+}
+
+/// Timeout and retries for fetching collections and items.
+pub fn fetch_policy() -> FetchPolicy {
+    let config = CONFIG_INSTANCE.get_or_init(ConfigFile::default);
+    FetchPolicy {
+        timeout: Duration::from_secs(config.timeout_secs),
+        retries: config.retries,
+        ..FetchPolicy::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_load_config() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE);
+        assert_eq!(load_config(&path).unwrap().region, "ap-southeast-2");
+
+        fs::write(&path, "region = \"us-west-2\"\n").unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.region, "us-west-2");
+        assert_eq!(config.skip_signature, "true");
+        assert_eq!(config.retries, 3);
+
+        fs::write(&path, ConfigFile::default().to_toml().unwrap()).unwrap();
+        assert_eq!(load_config(&path).unwrap().region, "ap-southeast-2");
+
+        fs::write(&path, "[stac_api]\nelevation = \"http://localhost:8080\"\n").unwrap();
+        let config = load_config(&path).unwrap();
+        assert_eq!(config.stac_api["elevation"], "http://localhost:8080");
+
+        fs::write(&path, "region = 3").unwrap();
+        assert!(load_config(&path).is_err());
+    }
+}
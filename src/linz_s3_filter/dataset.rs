@@ -1,12 +1,174 @@
+use chrono::{DateTime, Utc};
 use clap::ValueEnum;
-use stac::Item;
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use stac::{Collection, Item};
+use std::str::FromStr;
+
+use crate::linz_s3_filter::utils::{collection_date_range, extract_value_before_m};
 
 pub struct MatchingItems {
     pub title: String,
+    pub collection: Collection,
+    pub items: Vec<Item>,
+}
+
+/// A single downloadable asset of a matched item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tile {
+    /// Absolute href of the asset.
+    pub href: String,
+    /// Size in bytes, from `file:size` or a HEAD request.
+    pub size: Option<u64>,
+    /// Id of the STAC item the asset belongs to.
+    pub item_id: String,
+    /// Href of the STAC item the asset belongs to.
+    pub item_href: Option<String>,
+    /// STAC `file:checksum` of the asset, a multihash in hex.
+    pub checksum: Option<String>,
+    /// `updated` timestamp of the asset, or of the item if the asset has none.
+    pub updated: Option<String>,
+}
+
+/// A dataset that matched a search, with the tile assets of its items.
+pub struct DatasetMatch {
+    pub title: String,
+    pub collection: Collection,
     pub items: Vec<Item>,
+    pub tiles: Vec<Tile>,
+}
+
+impl DatasetMatch {
+    /// Resolution in metres parsed from the title, or `f64::MAX` if the title has none.
+    pub fn resolution(&self) -> f64 {
+        extract_value_before_m(&self.title)
+    }
+
+    /// First start and last end date of the collection's temporal extent.
+    pub fn date_range(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        collection_date_range(&self.collection)
+    }
+
+    /// Total size of all tiles, if the size of every tile is known.
+    pub fn known_size(&self) -> Option<u64> {
+        self.tiles.iter().map(|tile| tile.size).sum()
+    }
+
+    /// Bytes of the tiles with a known size, and the number of tiles of unknown size.
+    pub fn size_estimate(&self) -> (u64, usize) {
+        self.tiles
+            .iter()
+            .fold((0, 0), |(bytes, unknown), tile| match tile.size {
+                Some(size) => (bytes + size, unknown),
+                None => (bytes, unknown + 1),
+            })
+    }
+
+    /// Human readable size, marked as a lower bound if some tile sizes are unknown.
+    pub fn size_label(&self) -> String {
+        match self.size_estimate() {
+            (bytes, 0) => HumanBytes(bytes).to_string(),
+            (0, _) => "unknown".to_string(),
+            (bytes, _) => format!(">{}", HumanBytes(bytes)),
+        }
+    }
+
+    /// Fraction (0.0 to 1.0) of the search area covered by the bounding boxes of the matched items.
+    /// A point search is fully covered by any match.
+    pub fn coverage(&self, lon_min: f64, lat_min: f64, lon_max: f64, lat_max: f64) -> f64 {
+        let area = (lon_max - lon_min) * (lat_max - lat_min);
+        if area <= 0.0 {
+            return if self.items.is_empty() { 0.0 } else { 1.0 };
+        }
+        let covered: f64 = self
+            .items
+            .iter()
+            .filter_map(|item| item.bbox.as_ref())
+            .map(|bbox| {
+                let width = bbox.xmax().min(lon_max) - bbox.xmin().max(lon_min);
+                let height = bbox.ymax().min(lat_max) - bbox.ymin().max(lat_min);
+                width.max(0.0) * height.max(0.0)
+            })
+            .sum();
+        (covered / area).min(1.0)
+    }
+}
+
+/// A `key=value` condition on a collection field, such as `linz:region=otago`.
+/// Top-level fields are checked first, then the collection summaries. Strings compare
+/// case-insensitively, numbers numerically, and lists match if any element does.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PropertyFilter {
+    pub key: String,
+    pub value: String,
+}
+
+/// The fields of a collection as JSON, to match property filters against. Serializes the
+/// whole collection, so it is done once for all filters.
+pub fn collection_fields(collection: &Collection) -> Map<String, Value> {
+    match serde_json::to_value(collection) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+impl PropertyFilter {
+    /// Whether the field, or summary, of the collection `fields` matches the filter.
+    pub fn matches(&self, fields: &Map<String, Value>) -> bool {
+        let field = fields.get(&self.key).or_else(|| {
+            fields
+                .get("summaries")
+                .and_then(|summaries| summaries.get(&self.key))
+        });
+        field.is_some_and(|field| self.matches_value(field))
+    }
+
+    fn matches_value(&self, value: &Value) -> bool {
+        match value {
+            Value::String(s) => s.eq_ignore_ascii_case(&self.value),
+            Value::Number(n) => n
+                .as_f64()
+                .zip(f64::from_str(&self.value).ok())
+                .is_some_and(|(a, b)| a == b),
+            Value::Bool(b) => b.to_string() == self.value.to_lowercase(),
+            Value::Array(values) => values.iter().any(|value| self.matches_value(value)),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for PropertyFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(PropertyFilter {
+                key: key.trim().to_string(),
+                value: value.trim().to_string(),
+            }),
+            _ => Err(format!("Expected a filter of the form key=value: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for PropertyFilter {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PropertyFilter> for String {
+    fn from(filter: PropertyFilter) -> Self {
+        format!("{}={}", filter.key, filter.value)
+    }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BucketName {
     Elevation,
     Imagery,
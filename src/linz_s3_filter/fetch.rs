@@ -0,0 +1,292 @@
+use log::{debug, warn};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use stac::SelfHref;
+use stac_io::StacStore;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::MyError;
+use crate::progress;
+
+/// How STAC documents are fetched: how long each request may take and how often a failed one is tried again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FetchPolicy {
+    pub timeout: Duration,
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each following one.
+    pub backoff: Duration,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        FetchPolicy {
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+impl FetchPolicy {
+    /// Runs `request` for `url` until it succeeds, is rejected for good or runs out of retries.
+    pub async fn run<T, F, Fut>(&self, url: &str, mut request: F) -> Result<T, MyError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, MyError>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            progress::stats().add_request();
+            let result = match tokio::time::timeout(self.timeout, request()).await {
+                Ok(result) => result,
+                Err(_) => Err(MyError::Timeout(url.to_string(), self.timeout)),
+            };
+            match result {
+                Err(e) if attempt < self.retries && is_retryable(&e) => {
+                    attempt += 1;
+                    debug!(
+                        "Retrying {} in {:?} ({}/{}): {}",
+                        url, backoff, attempt, self.retries, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Reads a STAC object from the store.
+    pub async fn get<T: DeserializeOwned + SelfHref>(
+        &self,
+        store: &StacStore,
+        url: &str,
+    ) -> Result<T, MyError> {
+        self.run(url, || async { Ok(store.get(url).await?) }).await
+    }
+}
+
+/// Whether a request that failed with `error` could succeed when tried again. Requests the server
+/// rejected, other than for rate limiting, and responses that could not be parsed fail the same way again.
+fn is_retryable(error: &MyError) -> bool {
+    match error {
+        MyError::HttpError(e) => is_retryable_http(e),
+        MyError::StacIOError(e) => is_transport_error(e),
+        MyError::JsonError(_) => false,
+        _ => true,
+    }
+}
+
+fn is_retryable_http(error: &reqwest::Error) -> bool {
+    if error.is_decode() {
+        return false;
+    }
+    error
+        .status()
+        .is_none_or(|status| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// Whether a store error was caused by the connection, a timeout, a server error or rate
+/// limiting. Missing objects and documents that could not be parsed are not.
+fn is_transport_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            return is_retryable_http(error);
+        }
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            return matches!(
+                error.kind(),
+                ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::BrokenPipe
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// A collection or item that could not be read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchFailure {
+    pub url: String,
+    pub error: String,
+}
+
+/// Collections and items that could not be read, shared between the tasks of a search.
+#[derive(Clone, Debug, Default)]
+pub struct FetchFailures {
+    collections: Arc<Mutex<Vec<FetchFailure>>>,
+    items: Arc<Mutex<Vec<FetchFailure>>>,
+}
+
+impl FetchFailures {
+    /// Failures of a new search, starting with the collections that could not be read for this one.
+    pub fn for_search(&self) -> Self {
+        FetchFailures {
+            collections: Arc::new(Mutex::new(self.collections())),
+            items: Arc::default(),
+        }
+    }
+
+    pub fn add_collection(&self, url: &str, error: &MyError) {
+        warn!("Could not read collection {}: {}", url, error);
+        push(&self.collections, url, error);
+    }
+
+    pub fn add_item(&self, url: &str, error: &MyError) {
+        debug!("Could not read item {}: {}", url, error);
+        push(&self.items, url, error);
+    }
+
+    pub fn collections(&self) -> Vec<FetchFailure> {
+        self.collections.lock().unwrap().clone()
+    }
+
+    pub fn items(&self) -> Vec<FetchFailure> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.collections.lock().unwrap().is_empty() && self.items.lock().unwrap().is_empty()
+    }
+
+    /// Logs everything that could not be read.
+    pub fn report(&self) {
+        if self.is_empty() {
+            return;
+        }
+        let (collections, items) = (self.collections(), self.items());
+        warn!(
+            "{} collections and {} items could not be read:",
+            collections.len(),
+            items.len()
+        );
+        for (kind, failures) in [("collection", collections), ("item", items)] {
+            for failure in failures {
+                warn!("  {} {}: {}", kind, failure.url, failure.error);
+            }
+        }
+    }
+
+    /// `MyError::IncompleteSearch` if anything could not be read.
+    pub fn check(&self) -> Result<(), MyError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        Err(MyError::IncompleteSearch {
+            collections: self.collections.lock().unwrap().len(),
+            items: self.items.lock().unwrap().len(),
+        })
+    }
+}
+
+fn push(failures: &Mutex<Vec<FetchFailure>>, url: &str, error: &MyError) {
+    failures.lock().unwrap().push(FetchFailure {
+        url: url.to_string(),
+        error: error.to_string(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(retries: u32) -> FetchPolicy {
+        FetchPolicy {
+            timeout: Duration::from_millis(50),
+            retries,
+            backoff: Duration::from_millis(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_retries() {
+        let attempts = AtomicU32::new(0);
+        let result = policy(2)
+            .run("a-url", || async {
+                match attempts.fetch_add(1, Ordering::Relaxed) {
+                    0 => Err(MyError::Cancelled),
+                    // Too slow, times out
+                    1 => {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        Ok(1)
+                    }
+                    _ => Ok(2),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(attempts.load(Ordering::Relaxed), 3);
+
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy(1)
+            .run("a-url", || async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(MyError::Timeout(url, _)) if url == "a-url"));
+        assert_eq!(attempts.load(Ordering::Relaxed), 2);
+
+        // Parse errors are not retried
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = policy(3)
+            .run("a-url", || async {
+                attempts.fetch_add(1, Ordering::Relaxed);
+                Err(serde_json::from_str::<()>("{").unwrap_err().into())
+            })
+            .await;
+        assert!(matches!(result, Err(MyError::JsonError(_))));
+        assert_eq!(attempts.load(Ordering::Relaxed), 1);
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("store error")]
+    struct StoreError(#[source] std::io::Error);
+
+    #[test]
+    fn test_is_transport_error() {
+        let error = |kind| StoreError(std::io::Error::new(kind, "an error"));
+        assert!(is_transport_error(&error(ErrorKind::ConnectionReset)));
+        assert!(is_transport_error(&error(ErrorKind::TimedOut)));
+        assert!(!is_transport_error(&error(ErrorKind::NotFound)));
+        assert!(!is_transport_error(&error(ErrorKind::InvalidData)));
+        assert!(!is_transport_error(
+            &serde_json::from_str::<()>("{").unwrap_err()
+        ));
+    }
+
+    #[test]
+    fn test_fetch_failures() {
+        let failures = FetchFailures::default();
+        assert!(failures.check().is_ok());
+        failures.add_collection("collection.json", &MyError::Cancelled);
+
+        let search = failures.for_search();
+        search.add_item("item.json", &MyError::Cancelled);
+        assert!(matches!(
+            search.check(),
+            Err(MyError::IncompleteSearch {
+                collections: 1,
+                items: 1
+            })
+        ));
+        // Item failures stay with their search
+        assert!(failures.items().is_empty());
+        assert_eq!(failures.for_search().items(), vec![]);
+        assert_eq!(search.items()[0].url, "item.json");
+    }
+}
@@ -0,0 +1,178 @@
+use rstar::{RTree, RTreeObject, AABB};
+use sanitize_filename::sanitize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stac::{Collection, Href, Item, SelfHref};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+use crate::error::MyError;
+
+/// Items of a collection stored in an R-tree by their bbox, so spatial searches
+/// can be answered without fetching the item JSON again.
+#[derive(Serialize, Deserialize)]
+pub struct ItemIndex {
+    collection_id: String,
+    /// Hash of the collection JSON the index was built from.
+    fingerprint: String,
+    tree: RTree<IndexedItem>,
+    /// Items without a bbox, which only match searches without a spatial filter.
+    unlocated: Vec<IndexedItem>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct IndexedItem {
+    /// Position of the item in the collection, to keep results in link order.
+    order: usize,
+    bbox: [f64; 4],
+    href: Option<String>,
+    item: Item,
+}
+
+impl RTreeObject for IndexedItem {
+    type Envelope = AABB<[f64; 2]>;
+
+    fn envelope(&self) -> Self::Envelope {
+        let [xmin, ymin, xmax, ymax] = self.bbox;
+        AABB::from_corners([xmin, ymin], [xmax, ymax])
+    }
+}
+
+impl IndexedItem {
+    fn into_item(self) -> Item {
+        let mut item = self.item;
+        *item.self_href_mut() = self.href.map(Href::from);
+        item
+    }
+}
+
+/// Identifies a version of a collection. Any change to the collection, such as an added
+/// or updated item link, gives a new fingerprint.
+pub fn fingerprint(collection: &Collection) -> String {
+    let json = serde_json::to_vec(collection).unwrap_or_default();
+    format!("{:x}", Sha256::digest(json))
+}
+
+fn index_path(dir: &Path, collection_id: &str) -> PathBuf {
+    dir.join(format!("{}.json", sanitize(collection_id)))
+}
+
+impl ItemIndex {
+    pub fn build(collection: &Collection, items: Vec<Item>) -> Self {
+        let mut located = vec![];
+        let mut unlocated = vec![];
+        for (order, item) in items.into_iter().enumerate() {
+            let bbox = item
+                .bbox
+                .map(|bbox| [bbox.xmin(), bbox.ymin(), bbox.xmax(), bbox.ymax()]);
+            let indexed = IndexedItem {
+                order,
+                bbox: bbox.unwrap_or_default(),
+                href: item.self_href().map(|href| href.to_string()),
+                item,
+            };
+            match bbox {
+                Some(_) => located.push(indexed),
+                None => unlocated.push(indexed),
+            }
+        }
+        ItemIndex {
+            collection_id: collection.id.clone(),
+            fingerprint: fingerprint(collection),
+            tree: RTree::bulk_load(located),
+            unlocated,
+        }
+    }
+
+    /// Reads the index of a collection from `dir`. `None` if there is none, or if it was
+    /// built from a different version of the collection.
+    pub fn load(dir: &Path, collection: &Collection) -> Option<Self> {
+        let json = fs::read(index_path(dir, &collection.id)).ok()?;
+        let index: ItemIndex = serde_json::from_slice(&json).ok()?;
+        (index.fingerprint == fingerprint(collection)).then_some(index)
+    }
+
+    /// Writes the index to `dir`. It is written to a temporary file first and renamed into
+    /// place, so other runs never read a partly written index.
+    pub fn write(&self, dir: &Path) -> Result<(), MyError> {
+        fs::create_dir_all(dir)?;
+        let mut file = NamedTempFile::new_in(dir)?;
+        file.write_all(&serde_json::to_vec(self)?)?;
+        file.persist(index_path(dir, &self.collection_id))
+            .map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// All items, in the order of the collection's links.
+    pub fn items(mut self) -> Vec<Item> {
+        let mut items: Vec<_> = self.tree.drain().chain(self.unlocated).collect();
+        items.sort_by_key(|item| item.order);
+        items.into_iter().map(IndexedItem::into_item).collect()
+    }
+
+    /// Items whose bbox intersects the given box, in the order of the collection's links.
+    pub fn items_within(
+        &self,
+        lon_min: f64,
+        lat_min: f64,
+        lon_max: f64,
+        lat_max: f64,
+    ) -> Vec<Item> {
+        let envelope = AABB::from_corners([lon_min, lat_min], [lon_max, lat_max]);
+        let mut items: Vec<_> = self
+            .tree
+            .locate_in_envelope_intersecting(&envelope)
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| item.order);
+        items.into_iter().map(IndexedItem::into_item).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stac::Bbox;
+    use tempfile::tempdir;
+
+    fn item(id: &str, bbox: Option<Bbox>) -> Item {
+        let mut item = Item::new(id);
+        item.bbox = bbox;
+        *item.self_href_mut() = Some(Href::from(format!("https://example.com/{}.json", id)));
+        item
+    }
+
+    #[test]
+    fn test_item_index() {
+        let dir = tempdir().unwrap();
+        let mut collection = Collection::new("an-id", "a description");
+        let items = vec![
+            item("east", Some(Bbox::new(172.0, -44.0, 173.0, -43.0))),
+            item("no-bbox", None),
+            item("west", Some(Bbox::new(170.0, -46.0, 171.0, -45.0))),
+        ];
+        assert!(ItemIndex::load(dir.path(), &collection).is_none());
+        ItemIndex::build(&collection, items)
+            .write(dir.path())
+            .unwrap();
+
+        let index = ItemIndex::load(dir.path(), &collection).unwrap();
+        let found = index.items_within(170.5, -45.5, 172.0, -45.0);
+        let ids: Vec<_> = found.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids, ["west"]);
+        assert_eq!(
+            found[0].self_href().unwrap().to_string(),
+            "https://example.com/west.json"
+        );
+        // A box touching the edge of an item matches, as when testing the bbox of a fetched item
+        assert_eq!(index.items_within(173.0, -43.0, 173.0, -43.0).len(), 1);
+        let ids: Vec<_> = index.items().into_iter().map(|item| item.id).collect();
+        assert_eq!(ids, ["east", "no-bbox", "west"]);
+
+        // A changed collection makes the index stale
+        collection.title = Some("A new title".to_string());
+        assert!(ItemIndex::load(dir.path(), &collection).is_none());
+    }
+}
@@ -1,22 +1,60 @@
 use crate::error::MyError;
-use crate::linz_s3_filter::dataset::BucketName;
-use crate::linz_s3_filter::reporter::Reporter;
-use crate::linz_s3_filter::utils::{get_hrefs, process_collection};
-use log::{debug, info};
-use stac::{Catalog, Collection, Links};
+use crate::linz_s3_filter::dataset::{collection_fields, BucketName, DatasetMatch, PropertyFilter};
+use crate::linz_s3_filter::fetch::{FetchFailures, FetchPolicy};
+use crate::linz_s3_filter::reporter::{spawn_reporting, Reporter, SearchProgress};
+use crate::linz_s3_filter::stac_api::StacApi;
+use crate::linz_s3_filter::utils::{
+    collection_date_range, dataset_match, process_collection, sort_datasets, with_self_href, Bounds,
+};
+use crate::progress::{self, ProgressEvent};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream};
+use futures::{future, StreamExt};
+use log::{debug, info, warn};
+use stac::{Catalog, Collection, Item, Links, SelfHref};
 use stac_io::StacStore;
-use std::sync::{atomic::Ordering, Arc};
-use tokio::sync::Semaphore;
-use tokio::time::{self, Duration};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
-use crate::linz_s3_filter::bucket_config::get_opts;
+use crate::linz_s3_filter::bucket_config::{
+    fetch_policy, geoparquet_path, get_opts, index_dir, stac_api_url,
+};
 
 pub struct LinzBucket {
     pub store: StacStore,
     pub collections: Vec<Collection>,
-    pub filtered_collections: Option<Vec<Collection>>,
-    pub reporter: Reporter, // Use Mutex for interior mutability
     pub permits: usize,
+    /// STAC API used for searching, instead of crawling the static catalog.
+    pub api: Option<StacApi>,
+    /// Directory of the item indexes, `None` to always fetch items.
+    pub index_dir: Option<PathBuf>,
+    /// Items read from a stac-geoparquet file, by collection id.
+    pub preloaded_items: HashMap<String, Vec<Item>>,
+    pub fetch_policy: FetchPolicy,
+    /// Collections of the catalog that could not be read.
+    pub failures: FetchFailures,
+}
+
+fn permits(concurrency_multiplier: Option<usize>) -> Result<usize, MyError> {
+    let permits = concurrency_multiplier.unwrap_or(1) * num_cpus::get();
+    if permits == 0 {
+        return Err(MyError::ThreadPermitError(
+            "Concurrency multiplier resulted in zero permits",
+        ));
+    }
+    if permits > Semaphore::MAX_PERMITS {
+        return Err(MyError::ThreadPermitError(
+            "Concurrency multiplier is too high, exceeds maximum permitted threads",
+        ));
+    }
+    debug!("Number of permits: {}", permits);
+    Ok(permits)
 }
 
 impl LinzBucket {
@@ -24,17 +62,40 @@ impl LinzBucket {
         dataset: BucketName,
         concurrency_multiplier: Option<usize>,
     ) -> Result<Self, MyError> {
+        Self::initialise_catalog_with_cancel(
+            dataset,
+            concurrency_multiplier,
+            CancellationToken::new(),
+        )
+        .await
+    }
+
+    /// Like `initialise_catalog`, but stops with `MyError::Cancelled` when `cancel` is cancelled.
+    /// Searches of the bucket are cancelled through their own `TileQuery::cancel`.
+    pub async fn initialise_catalog_with_cancel(
+        dataset: BucketName,
+        concurrency_multiplier: Option<usize>,
+        cancel: CancellationToken,
+    ) -> Result<Self, MyError> {
+        if let Some(url) = stac_api_url(dataset) {
+            return Self::initialise_stac_api(&url, concurrency_multiplier, cancel).await;
+        }
         info!("Initialising Catalog...");
         let catalog_url = format!("{}/catalog.json", dataset.as_str());
         let options = get_opts();
+        let fetch_policy = fetch_policy();
+        let failures = FetchFailures::default();
 
         let (store, _) = stac_io::parse_href_opts(&catalog_url, options)?;
 
-        let mut catalog: Catalog = store.get(&catalog_url).await?;
+        let mut catalog: Catalog = tokio::select! {
+            catalog = fetch_policy.get(&store, &catalog_url) => catalog?,
+            _ = cancel.cancelled() => return Err(MyError::Cancelled),
+        };
         info!("ID: {}", catalog.id);
         info!("Title: {}", catalog.title.as_deref().unwrap_or("N/A"));
         info!("Description: {}", catalog.description);
-        catalog.make_links_absolute().unwrap();
+        catalog.make_links_absolute()?;
         let links = catalog.links();
         let mut urls = Vec::with_capacity(links.len());
 
@@ -43,18 +104,7 @@ impl LinzBucket {
                 urls.push(link.href.clone());
             }
         }
-        let permits = concurrency_multiplier.unwrap_or(1) * num_cpus::get();
-        if permits == 0 {
-            return Err(MyError::ThreadPermitError(
-                "Concurrency multiplier resulted in zero permits",
-            ));
-        }
-        if permits > Semaphore::MAX_PERMITS {
-            return Err(MyError::ThreadPermitError(
-                "Concurrency multiplier is too high, exceeds maximum permitted threads",
-            ));
-        }
-        debug!("Number of permits: {}", permits);
+        let permits = permits(concurrency_multiplier)?;
         let semaphore = Arc::new(Semaphore::new(permits));
 
         let mut handles = Vec::with_capacity(urls.len());
@@ -62,20 +112,25 @@ impl LinzBucket {
             let store = store.clone();
 
             let semaphore = semaphore.clone();
+            let cancel = cancel.clone();
+            let failures = failures.clone();
             let handle = tokio::spawn(async move {
-                let _permit = semaphore.acquire().await.unwrap();
-                let result: Result<Collection, stac_io::Error> = store.clone().get(url).await;
+                let _permit = tokio::select! {
+                    permit = semaphore.acquire() => permit.ok()?,
+                    _ = cancel.cancelled() => return None,
+                };
+                let result: Result<Collection, MyError> = tokio::select! {
+                    result = fetch_policy.get(&store, &url) => result,
+                    _ = cancel.cancelled() => return None,
+                };
                 drop(_permit);
-                match result {
-                    Ok(mut collection) => {
-                        collection.make_links_absolute().unwrap();
-                        Some(collection)
-                    }
-                    Err(e) => {
-                        MyError::from(e).report();
-                        None
-                    }
-                }
+                let collection = result.and_then(|mut collection| {
+                    collection.make_links_absolute()?;
+                    Ok(collection)
+                });
+                collection
+                    .map_err(|e| failures.add_collection(&url, &e))
+                    .ok()
             });
             handles.push(handle);
         }
@@ -83,6 +138,9 @@ impl LinzBucket {
         for handle in handles {
             results.push(handle.await);
         }
+        if cancel.is_cancelled() {
+            return Err(MyError::Cancelled);
+        }
 
         let collections: Vec<_> = results
             .into_iter()
@@ -90,125 +148,315 @@ impl LinzBucket {
             .flatten()
             .collect();
 
-        let collections_total = collections.len();
         info!(
             "Total number of Collections in catalog: {}",
-            collections_total
+            collections.len()
         );
 
-        let bucket = LinzBucket {
+        let mut bucket = LinzBucket {
             store,
             collections,
-            filtered_collections: None,
-            reporter: Reporter::new(collections_total),
             permits,
+            api: None,
+            index_dir: index_dir(),
+            preloaded_items: HashMap::new(),
+            fetch_policy,
+            failures,
         };
+        if let Some(path) = geoparquet_path(dataset) {
+            if let Err(e) = bucket.load_geoparquet(&path) {
+                e.report();
+            }
+        }
 
         Ok(bucket)
     }
 
-    fn start_reporting(&self, reporter: Arc<Reporter>, semaphore: Arc<Semaphore>) {
-        tokio::spawn(async move {
-            let _permit = semaphore.acquire().await.unwrap();
-            reporter.add_thread();
-            let mut interval = time::interval(Duration::from_secs(1));
+    /// Reads the collections of a STAC API server. Searches then go through its `/search` endpoint.
+    pub async fn initialise_stac_api(
+        url: &str,
+        concurrency_multiplier: Option<usize>,
+        cancel: CancellationToken,
+    ) -> Result<Self, MyError> {
+        info!("Initialising STAC API {}...", url);
+        let (store, _) = stac_io::parse_href_opts(url, Vec::<(&str, String)>::new())?;
+        let fetch_policy = fetch_policy();
+        let api = StacApi::new(url).with_policy(fetch_policy);
+        let collections = tokio::select! {
+            collections = api.collections() => collections?,
+            _ = cancel.cancelled() => return Err(MyError::Cancelled),
+        };
+        info!(
+            "Total number of Collections in catalog: {}",
+            collections.len()
+        );
+        Ok(LinzBucket {
+            store,
+            collections,
+            permits: permits(concurrency_multiplier)?,
+            api: Some(api),
+            index_dir: None,
+            preloaded_items: HashMap::new(),
+            fetch_policy,
+            failures: FetchFailures::default(),
+        })
+    }
 
-            while !reporter.stop_flag.load(Ordering::Relaxed) {
-                interval.tick().await;
-                reporter.report();
+    /// Reads items from a stac-geoparquet file. Searches then use these items for their
+    /// collections instead of fetching item JSONs. Returns the number of items read.
+    ///
+    /// Collections whose item links differ from the items in the file have changed since it
+    /// was written. Their items are left out, so searches fetch them instead.
+    pub fn load_geoparquet(&mut self, path: &Path) -> Result<usize, MyError> {
+        let item_collection = stac::geoparquet::from_reader(File::open(path)?)?;
+        let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+        for item in item_collection.items {
+            let Some(collection_id) = item.collection.clone() else {
+                continue;
+            };
+            items
+                .entry(collection_id)
+                .or_default()
+                .push(with_self_href(item));
+        }
+        for collection in &self.collections {
+            let Some(preloaded) = items.remove(&collection.id) else {
+                continue;
+            };
+            if lists_items(collection, &preloaded) {
+                self.preloaded_items
+                    .insert(collection.id.clone(), preloaded);
+            } else {
+                warn!(
+                    "{} has changed since {} was written, fetching its items instead",
+                    collection.id,
+                    path.display()
+                );
             }
-            reporter.report_finished_thread();
-        });
+        }
+        let count = self.preloaded_items.values().map(Vec::len).sum();
+        info!(
+            "Read {} items of {} collections from {}",
+            count,
+            self.preloaded_items.len(),
+            path.display()
+        );
+        Ok(count)
     }
 
-    pub async fn get_tiles(
-        &mut self,
-        lat1_opt: Option<f64>,
-        lon1_opt: Option<f64>,
-        lat2_opt: Option<f64>,
-        lon2_opt: Option<f64>,
-    ) -> Vec<(Vec<String>, String)> {
-        let filtered_collections = self
-            .filtered_collections
-            .as_ref()
-            .unwrap_or(&self.collections);
-        self.reporter.reset_all(filtered_collections.len());
-        let reporter = Arc::new(self.reporter.clone());
+    /// The collections selected by the filters of `query`.
+    pub fn collections_matching(&self, query: &TileQuery) -> Vec<&Collection> {
+        self.collections
+            .iter()
+            .filter(|collection| query.matches(collection))
+            .collect()
+    }
+
+    pub async fn get_tiles(&self, query: &TileQuery) -> Vec<DatasetMatch> {
+        let mut datasets: Vec<_> = self
+            .get_tiles_stream(query)
+            .filter_map(|result| future::ready(result.map_err(|e| e.report()).ok()))
+            .collect()
+            .await;
+        sort_datasets(&mut datasets);
+        datasets
+    }
+
+    /// Like `get_tiles`, but yields each matching dataset as soon as its collection has been
+    /// searched, in the order the collections finish.
+    ///
+    /// Searches only share the loaded catalog, so several can run at once on one bucket, each
+    /// with its own query, progress, cancellation and worker pool.
+    ///
+    /// If any collection or item could not be read, the failures are logged and the stream
+    /// ends with `MyError::IncompleteSearch`.
+    pub fn get_tiles_stream(
+        &self,
+        query: &TileQuery,
+    ) -> BoxStream<'static, Result<DatasetMatch, MyError>> {
+        let collections = self.collections_matching(query);
+        let bounds = query.bounds;
+        if let Some(api) = &self.api {
+            let bbox = bounds
+                .map(|(lon_min, lat_min, lon_max, lat_max)| [lon_min, lat_min, lon_max, lat_max]);
+            let api = api.clone();
+            let collections: Vec<_> = collections.into_iter().cloned().collect();
+            let (start, end) = (query.start, query.end);
+            let cancel = query.cancel.clone();
+            return stream::once(async move {
+                match api.get_tiles(&collections, bbox, start, end, &cancel).await {
+                    Ok(tiles) => tiles.into_iter().map(Ok).collect(),
+                    Err(e) => vec![Err(e)],
+                }
+            })
+            .flat_map(stream::iter)
+            .inspect(report_match)
+            .boxed();
+        }
+        query.reporter.reset_all(collections.len());
+        let reporter = Arc::new(query.reporter.clone());
         let semaphore = Arc::new(Semaphore::new(self.permits)); // Limit concurrent threads
-        self.start_reporting(Arc::clone(&reporter), semaphore.clone());
+        spawn_reporting(Arc::clone(&reporter));
+        let failures = self.failures.for_search();
 
-        let mut handles = Vec::with_capacity(filtered_collections.len());
-        for collection in filtered_collections {
+        // Dropping the stream drops the set, which aborts the collection searches still running
+        let mut tasks = JoinSet::new();
+        for collection in collections {
             let ctx = CollectionTaskContext {
                 collection: collection.clone(),
                 store: self.store.clone(),
                 reporter: reporter.clone(),
                 semaphore: semaphore.clone(),
+                workers: self.permits,
+                index_dir: self.index_dir.clone(),
+                items: self.preloaded_items.get(&collection.id).cloned(),
+                cancel: query.cancel.clone(),
+                fetch_policy: self.fetch_policy,
+                failures: failures.clone(),
             };
-            let handle = tokio::spawn(async move {
-                process_collection(ctx, lon1_opt, lat1_opt, lon2_opt, lat2_opt).await
+            tasks.spawn(process_collection(ctx, bounds));
+        }
+        let results = stream::unfold(tasks, |mut tasks| async move {
+            let result = tasks.join_next().await?;
+            Some((result, tasks))
+        });
+        // Stops the progress report when the stream is finished or dropped
+        let stop_reporting = StopReporting(reporter.stop_flag.clone());
+        let finished = stream::once(async move {
+            reporter.report();
+            drop(stop_reporting);
+            info!("All collections processed");
+            failures.report();
+            failures.check().err().map(Err)
+        });
+        results
+            .map(|result| {
+                let result = result.unwrap_or_else(|e| {
+                    MyError::from(e).report();
+                    None
+                });
+                result.map(|result| Ok(dataset_match(result)))
+            })
+            .chain(finished)
+            .filter_map(future::ready)
+            .inspect(report_match)
+            .boxed()
+    }
+
+    pub async fn get_all_tiles(&self) -> Vec<DatasetMatch> {
+        self.get_tiles(&TileQuery::default()).await
+    }
+}
+
+/// What a search of a bucket looks for: the collections to search and the area of the tiles.
+/// The default searches all tiles of all collections.
+#[derive(Clone, Debug, Default)]
+pub struct TileQuery {
+    /// Only collections with one of these in their id or title, all collections if empty.
+    pub include: Vec<String>,
+    /// Leaves out collections with one of these in their id or title.
+    pub exclude: Vec<String>,
+    pub properties: Vec<PropertyFilter>,
+    /// Only tiles within `(lon_min, lat_min, lon_max, lat_max)`, and collections whose extent
+    /// intersects it.
+    pub bounds: Option<Bounds>,
+    /// Only collections whose temporal extent ends at or after this.
+    pub start: Option<DateTime<Utc>>,
+    /// Only collections whose temporal extent starts at or before this.
+    pub end: Option<DateTime<Utc>>,
+    /// Progress of the search of this query.
+    pub reporter: Reporter,
+    /// Cancelling this stops the search of this query. Collections stop fetching items, and
+    /// the stream yields the matches among the items read so far and then ends.
+    pub cancel: CancellationToken,
+}
+
+impl TileQuery {
+    /// Progress of the search of this query, updated while it runs.
+    pub fn progress(&self) -> watch::Receiver<SearchProgress> {
+        self.reporter.subscribe()
+    }
+
+    pub fn matches(&self, collection: &Collection) -> bool {
+        let name_contains = |filter: &String| {
+            collection.id.contains(filter.as_str())
+                || collection
+                    .title
+                    .as_deref()
+                    .unwrap_or("")
+                    .contains(filter.as_str())
+        };
+        let include = self.include.is_empty() || self.include.iter().any(name_contains);
+        let exclude = self.exclude.iter().any(name_contains);
+
+        let within_extent = self
+            .bounds
+            .is_none_or(|(lon_min, lat_min, lon_max, lat_max)| {
+                collection.extent.spatial.bbox.iter().any(|bbox| {
+                    bbox.xmin() <= lon_max
+                        && bbox.xmax() >= lon_min
+                        && bbox.ymin() <= lat_max
+                        && bbox.ymax() >= lat_min
+                })
             });
 
-            handles.push(handle);
+        let (collection_start, collection_end) = collection_date_range(collection);
+        let within_dates = self
+            .start
+            .zip(collection_end)
+            .is_none_or(|(start, collection_end)| collection_end >= start)
+            && self
+                .end
+                .zip(collection_start)
+                .is_none_or(|(end, collection_start)| collection_start <= end);
+
+        if !include || exclude || !within_extent || !within_dates {
+            return false;
         }
-        let mut results = Vec::with_capacity(handles.len());
-        for handle in handles {
-            results.push(handle.await.unwrap_or_else(|e| {
-                MyError::from(e).report();
-                None
-            }));
+        if self.properties.is_empty() {
+            return true;
         }
-        let results: Vec<_> = results.into_iter().flatten().collect();
-        self.reporter.stop_flag.store(true, Ordering::Relaxed);
-        info!("All collections processed");
-
-        get_hrefs(results).await
+        let fields = collection_fields(collection);
+        self.properties.iter().all(|filter| filter.matches(&fields))
     }
+}
 
-    pub async fn get_all_tiles(&mut self) -> Vec<(Vec<String>, String)> {
-        self.get_tiles(None, None, None, None).await
+/// Whether `items` are the items the collection links to. A collection that does not link
+/// its items cannot be checked, and its items are taken as they are.
+fn lists_items(collection: &Collection, items: &[Item]) -> bool {
+    let links: HashSet<String> = collection
+        .links()
+        .iter()
+        .filter(|link| link.is_item())
+        .map(|link| link.href.to_string())
+        .collect();
+    if links.is_empty() {
+        return true;
     }
+    let hrefs: HashSet<String> = items
+        .iter()
+        .filter_map(|item| item.self_href().map(|href| href.to_string()))
+        .collect();
+    links == hrefs
+}
 
-    pub fn set_collection_filter(
-        &mut self,
-        collection_name_filters: Option<&[String]>,
-        exclusion_filters: Option<&[String]>,
-        extent: Option<(f64, f64, Option<f64>, Option<f64>)>,
-    ) {
-        let filtered_collections: Vec<_> = self
-            .collections
-            .iter()
-            .filter(|collection| {
-                let include = collection_name_filters.is_none_or(|filters| {
-                    filters.is_empty()
-                        || filters.iter().any(|filter| {
-                            collection.id.contains(filter)
-                                || collection.title.as_deref().unwrap_or("").contains(filter)
-                        })
-                });
+/// Emits a progress event for each matching dataset.
+fn report_match(result: &Result<DatasetMatch, MyError>) {
+    if let Ok(dataset) = result {
+        progress::emit(&ProgressEvent::Match {
+            collection_id: &dataset.collection.id,
+            title: &dataset.title,
+            tiles: dataset.tiles.len(),
+            bytes: dataset.size_estimate().0,
+        });
+    }
+}
 
-                let exclude = exclusion_filters.is_some_and(|filters| {
-                    filters.iter().any(|filter| {
-                        collection.id.contains(filter)
-                            || collection.title.as_deref().unwrap_or("").contains(filter)
-                    })
-                });
+struct StopReporting(Arc<AtomicBool>);
 
-                let within_extent =
-                    extent.is_none_or(|(min_lat, min_lon, max_lat_opt, max_lon_opt)| {
-                        collection.extent.spatial.bbox.iter().any(|bbox| {
-                            bbox.xmin() <= max_lon_opt.unwrap_or(min_lon)
-                                && bbox.xmax() >= min_lon
-                                && bbox.ymin() <= max_lat_opt.unwrap_or(min_lat)
-                                && bbox.ymax() >= min_lat
-                        })
-                    });
-
-                include && !exclude && within_extent
-            })
-            .cloned()
-            .collect();
-        self.filtered_collections = Some(filtered_collections);
+impl Drop for StopReporting {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
     }
 }
 
@@ -217,5 +465,89 @@ pub struct CollectionTaskContext {
     pub collection: Collection,
     pub store: StacStore,
     pub reporter: Arc<Reporter>,
+    /// Permits of the item fetches of all collections of a search.
     pub semaphore: Arc<Semaphore>,
+    /// Item fetches of this collection in flight at once, the size of the shared pool.
+    pub workers: usize,
+    /// Directory of the item indexes, `None` to always fetch items.
+    pub index_dir: Option<PathBuf>,
+    /// Items of the collection read in advance, used instead of fetching them.
+    pub items: Option<Vec<Item>>,
+    pub cancel: CancellationToken,
+    pub fetch_policy: FetchPolicy,
+    pub failures: FetchFailures,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists_items() {
+        let item = |id: &str| {
+            let mut item = Item::new(id);
+            *item.self_href_mut() = Some(format!("https://example.com/{}.json", id).into());
+            item
+        };
+        let mut collection = Collection::new("a-collection", "a description");
+        assert!(lists_items(&collection, &[item("a")]));
+
+        for id in ["a", "b"] {
+            collection.links.push(stac::Link::new(
+                format!("https://example.com/{}.json", id),
+                "item",
+            ));
+        }
+        assert!(lists_items(&collection, &[item("b"), item("a")]));
+        // An item added to the collection since
+        assert!(!lists_items(&collection, &[item("a")]));
+        assert!(!lists_items(
+            &collection,
+            &[item("a"), item("b"), item("c")]
+        ));
+    }
+
+    #[test]
+    fn test_tile_query_matches() {
+        let mut collection = Collection::new("otago-id", "a description");
+        collection.title = Some("Otago LiDAR 1m DEM (2021)".to_string());
+        collection.extent.spatial.bbox = vec![stac::Bbox::new(168.5, -46.5, 171.0, -44.0)];
+
+        assert!(TileQuery::default().matches(&collection));
+        let query = |include: &[&str], exclude: &[&str], bounds| TileQuery {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+            bounds,
+            ..Default::default()
+        };
+        assert!(query(&["Otago", "Southland"], &[], None).matches(&collection));
+        assert!(query(&["otago-id"], &[], None).matches(&collection));
+        assert!(!query(&["Southland"], &[], None).matches(&collection));
+        assert!(!query(&["Otago"], &["DEM"], None).matches(&collection));
+        assert!(query(&[], &[], Some((170.0, -45.0, 172.0, -43.0))).matches(&collection));
+        assert!(!query(&[], &[], Some((174.0, -41.0, 175.0, -40.0))).matches(&collection));
+
+        let property = TileQuery {
+            properties: vec!["title=otago lidar 1m dem (2021)".parse().unwrap()],
+            ..Default::default()
+        };
+        assert!(property.matches(&collection));
+
+        collection.extent.temporal.interval = vec![[
+            Some("2021-03-01T00:00:00Z".parse().unwrap()),
+            Some("2021-09-30T00:00:00Z".parse().unwrap()),
+        ]];
+        let dates = |start: Option<&str>, end: Option<&str>| TileQuery {
+            start: start.map(|start| start.parse().unwrap()),
+            end: end.map(|end| end.parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(dates(Some("2021-06-01T00:00:00Z"), None).matches(&collection));
+        assert!(dates(None, Some("2021-03-01T00:00:00Z")).matches(&collection));
+        assert!(
+            dates(Some("2020-01-01T00:00:00Z"), Some("2022-01-01T00:00:00Z")).matches(&collection)
+        );
+        assert!(!dates(Some("2021-10-01T00:00:00Z"), None).matches(&collection));
+        assert!(!dates(None, Some("2020-12-31T00:00:00Z")).matches(&collection));
+    }
 }
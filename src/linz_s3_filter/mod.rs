@@ -1,17 +1,22 @@
 pub mod bucket_config;
 pub mod dataset;
+pub mod fetch;
+pub mod item_index;
 pub mod linz_bucket;
 pub mod reporter;
+pub mod stac_api;
 pub mod utils;
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use crate::linz_s3_filter::linz_bucket::CollectionTaskContext;
+    use fetch::{FetchFailures, FetchPolicy};
     use reporter::Reporter;
     use stac::Item;
     use stac_io::parse_href;
     use std::sync::{Arc, Once};
+    use tokio_util::sync::CancellationToken;
     use utils::{extract_value_before_m, process_collection};
     static INIT: Once = Once::new();
 
@@ -41,11 +46,14 @@ mod tests {
                 store,
                 reporter,
                 semaphore,
+                workers: 100,
+                index_dir: None,
+                items: None,
+                cancel: CancellationToken::new(),
+                fetch_policy: FetchPolicy::default(),
+                failures: FetchFailures::default(),
             },
-            Some(172.93),
-            Some(1.35),
-            None,
-            None,
+            Some((172.93, 1.35, 172.93, 1.35)),
         )
         .await;
 
@@ -53,6 +61,80 @@ mod tests {
         let matching_items = result.unwrap();
         assert_eq!(matching_items.title, "Test Collection");
     }
+    #[tokio::test]
+    async fn test_process_cancelled_collection() {
+        use stac::{Collection, Link};
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) = parse_href("http://127.0.0.1:9/catalog.json").unwrap();
+        let mut collection = Collection::new("an-id", "a description");
+        collection
+            .links
+            .push(Link::new("http://127.0.0.1:9/item.json", "item"));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let result = process_collection(
+            CollectionTaskContext {
+                collection: collection.clone(),
+                store,
+                reporter: Arc::new(Reporter::new(1)),
+                semaphore: Arc::new(tokio::sync::Semaphore::new(1)),
+                workers: 1,
+                index_dir: Some(dir.path().to_path_buf()),
+                items: None,
+                cancel,
+                fetch_policy: FetchPolicy::default(),
+                failures: FetchFailures::default(),
+            },
+            None,
+        )
+        .await;
+        assert!(result.is_none());
+        // Items given up on are not indexed as if the collection had none
+        assert!(item_index::ItemIndex::load(dir.path(), &collection).is_none());
+    }
+    #[tokio::test]
+    async fn test_process_collection_single_worker() {
+        use stac::{Collection, Link};
+        use std::time::Duration;
+        let (store, _) = parse_href("http://127.0.0.1:9/catalog.json").unwrap();
+        let mut collection = Collection::new("an-id", "a description");
+        for i in 0..20 {
+            collection.links.push(Link::new(
+                format!("http://127.0.0.1:9/item-{}.json", i),
+                "item",
+            ));
+        }
+        let reporter = Arc::new(Reporter::new(1));
+        let failures = FetchFailures::default();
+
+        let result = process_collection(
+            CollectionTaskContext {
+                collection,
+                store,
+                reporter: reporter.clone(),
+                semaphore: Arc::new(tokio::sync::Semaphore::new(1)),
+                workers: 1,
+                index_dir: None,
+                items: None,
+                cancel: CancellationToken::new(),
+                fetch_policy: FetchPolicy {
+                    timeout: Duration::from_secs(5),
+                    retries: 0,
+                    backoff: Duration::ZERO,
+                },
+                failures: failures.clone(),
+            },
+            None,
+        )
+        .await;
+        assert!(result.is_none());
+        let progress = reporter.snapshot();
+        assert_eq!((progress.items_read, progress.items_total), (20, 20));
+        assert_eq!(progress.collections_read, 1);
+        assert_eq!(progress.active_workers, 0);
+        assert_eq!(failures.items().len(), 20);
+    }
     #[test]
     fn test_extract_value_before_m() {
         init_logger();
@@ -78,12 +160,13 @@ mod tests {
 
         let results = vec![dataset::MatchingItems {
             title: "Test Collection".to_string(),
+            collection: stac::Collection::new("an-id", "a description"),
             items,
         }];
 
         let hrefs = utils::get_hrefs(results).await;
         assert_eq!(hrefs.len(), 1);
-        assert_eq!(hrefs[0].1, "Test Collection");
+        assert_eq!(hrefs[0].title, "Test Collection");
     }
     #[tokio::test]
     async fn test_get_hrefs_sorting() {
@@ -97,14 +180,17 @@ mod tests {
         let matching_items = vec![
             dataset::MatchingItems {
                 title: "title 10m 2020".to_string(),
+                collection: stac::Collection::new("id1", "a description"),
                 items: vec![item1.clone()],
             },
             dataset::MatchingItems {
                 title: "title 5m 2020".to_string(),
+                collection: stac::Collection::new("id2", "a description"),
                 items: vec![item2.clone()],
             },
             dataset::MatchingItems {
                 title: "another title 10m 2020".to_string(),
+                collection: stac::Collection::new("id3", "a description"),
                 items: vec![item3.clone()],
             },
         ];
@@ -114,8 +200,60 @@ mod tests {
 
         // Verify the sorting order
         assert_eq!(hrefs.len(), 3);
-        assert_eq!(hrefs[0].1, "title 5m 2020");
-        assert_eq!(hrefs[1].1, "another title 10m 2020");
-        assert_eq!(hrefs[2].1, "title 10m 2020");
+        assert_eq!(hrefs[0].title, "title 5m 2020");
+        assert_eq!(hrefs[1].title, "another title 10m 2020");
+        assert_eq!(hrefs[2].title, "title 10m 2020");
+    }
+    #[test]
+    fn test_dataset_size_estimate() {
+        use dataset::{DatasetMatch, Tile};
+        let tile = |size| Tile {
+            href: "tile.tif".to_string(),
+            size,
+            item_id: "an-id".to_string(),
+            item_href: None,
+            checksum: None,
+            updated: None,
+        };
+        let mut dataset = DatasetMatch {
+            title: "Test Collection".to_string(),
+            collection: stac::Collection::new("an-id", "a description"),
+            items: vec![],
+            tiles: vec![tile(Some(1024)), tile(Some(2048))],
+        };
+        assert_eq!(dataset.known_size(), Some(3072));
+        assert_eq!(dataset.size_label(), "3.00 KiB");
+
+        dataset.tiles.push(tile(None));
+        assert_eq!(dataset.known_size(), None);
+        assert_eq!(dataset.size_estimate(), (3072, 1));
+        assert_eq!(dataset.size_label(), ">3.00 KiB");
+    }
+    #[test]
+    fn test_property_filter() {
+        use dataset::PropertyFilter;
+        let mut collection = stac::Collection::new("an-id", "a description");
+        collection
+            .additional_fields
+            .insert("linz:region".to_string(), "otago".into());
+        collection.additional_fields.insert(
+            "linz:security_classification".to_string(),
+            "unclassified".into(),
+        );
+        let mut summaries = serde_json::Map::new();
+        summaries.insert("gsd".to_string(), serde_json::json!([1, 2]));
+        collection.summaries = Some(summaries);
+
+        let fields = dataset::collection_fields(&collection);
+        let filter = |s: &str| s.parse::<PropertyFilter>().unwrap();
+        assert!(filter("linz:region=Otago").matches(&fields));
+        assert!(!filter("linz:region=canterbury").matches(&fields));
+        assert!(filter("gsd=2").matches(&fields));
+        assert!(filter("gsd=1.0").matches(&fields));
+        assert!(!filter("gsd=0.5").matches(&fields));
+        assert!(filter("id=an-id").matches(&fields));
+        assert!(!filter("linz:lifecycle=completed").matches(&fields));
+        assert!("no-equals".parse::<PropertyFilter>().is_err());
+        assert!("=value".parse::<PropertyFilter>().is_err());
     }
 }
@@ -3,16 +3,41 @@ use std::sync::{
     Arc,
 };
 
+use indicatif::{ProgressBar, ProgressStyle};
 use log::info;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
 
-#[derive(Clone)]
+use crate::progress::{self, ProgressEvent};
+
+/// Progress of a search at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchProgress {
+    pub collections_read: usize,
+    pub collections_total: usize,
+    pub items_read: usize,
+    pub items_total: usize,
+    /// Item fetches holding a permit of the worker pool.
+    pub active_workers: usize,
+}
+
+/// Progress of one search, shared by its tasks.
+#[derive(Clone, Debug)]
 pub struct Reporter {
     urls_read: Arc<AtomicUsize>,
     urls_total: Arc<AtomicUsize>,
     collections_read: Arc<AtomicUsize>,
     open_threads: Arc<AtomicUsize>,
-    pub collections_total: usize,
+    collections_total: Arc<AtomicUsize>,
     pub stop_flag: Arc<AtomicBool>,
+    updates: Arc<watch::Sender<SearchProgress>>,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Reporter::new(0)
+    }
 }
 
 impl Reporter {
@@ -22,20 +47,57 @@ impl Reporter {
             urls_total: Arc::new(AtomicUsize::new(0)),
             open_threads: Arc::new(AtomicUsize::new(0)),
             collections_read: Arc::new(AtomicUsize::new(0)),
-            collections_total,
+            collections_total: Arc::new(AtomicUsize::new(collections_total)),
             stop_flag: Arc::new(AtomicBool::new(false)),
+            updates: Arc::new(watch::channel(SearchProgress::default()).0),
+        }
+    }
+
+    pub fn snapshot(&self) -> SearchProgress {
+        SearchProgress {
+            collections_read: self.collections_read.load(Ordering::Relaxed),
+            collections_total: self.collections_total.load(Ordering::Relaxed),
+            items_read: self.urls_read.load(Ordering::Relaxed),
+            items_total: self.urls_total.load(Ordering::Relaxed),
+            active_workers: self.open_threads.load(Ordering::Relaxed),
         }
     }
 
+    /// Progress of the search, updated by the reporting task while it runs.
+    pub fn subscribe(&self) -> watch::Receiver<SearchProgress> {
+        self.updates.subscribe()
+    }
+
+    /// Sends the current progress to the subscribers, if it changed.
+    fn publish(&self) {
+        let snapshot = self.snapshot();
+        self.updates.send_if_modified(|progress| {
+            let modified = *progress != snapshot;
+            *progress = snapshot;
+            modified
+        });
+    }
+
     pub fn report(&self) {
-        if !self.stop_flag.load(Ordering::Relaxed) {
+        if self.stop_flag.load(Ordering::Relaxed) {
+            return;
+        }
+        let progress = self.snapshot();
+        if progress::is_json() {
+            progress::emit(&ProgressEvent::Search {
+                collections_read: progress.collections_read,
+                collections_total: progress.collections_total,
+                items_read: progress.items_read,
+                items_total: progress.items_total,
+            });
+        } else {
             info!(
                 "Reporting: {}/{} Collections read, {}/{} URLS read, Open threads:{}",
-                self.collections_read.load(Ordering::Relaxed),
-                self.collections_total,
-                self.urls_read.load(Ordering::Relaxed),
-                self.urls_total.load(Ordering::Relaxed),
-                self.open_threads.load(Ordering::Relaxed)
+                progress.collections_read,
+                progress.collections_total,
+                progress.items_read,
+                progress.items_total,
+                progress.active_workers
             );
         }
     }
@@ -72,14 +134,92 @@ impl Reporter {
         self.urls_total.store(0, Ordering::Relaxed);
     }
 
-    pub fn reset_all(&mut self, collections_total: usize) {
-        self.collections_total = collections_total;
+    /// Starts reporting a new search of `collections_total` collections.
+    pub fn reset_all(&self, collections_total: usize) {
+        self.collections_total
+            .store(collections_total, Ordering::Relaxed);
+        self.stop_flag.store(false, Ordering::Relaxed);
         self.reset_collection_read();
         self.reset_urls_read();
         self.reset_urls_total();
-        info!("Collections to be read: {}", self.collections_total);
+        info!("Collections to be read: {}", collections_total);
     }
 }
+
+/// Reports the progress of a search on its own task until `stop_flag` is set: to subscribers,
+/// and as progress bars, log lines or JSON events. It does not take part in the worker pool.
+pub fn spawn_reporting(reporter: Arc<Reporter>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        // Bars are redrawn often, log lines and JSON events once a second
+        let bars = SearchBars::new();
+        let mut interval = time::interval(match bars {
+            Some(_) => Duration::from_millis(100),
+            None => Duration::from_secs(1),
+        });
+
+        while !reporter.stop_flag.load(Ordering::Relaxed) {
+            interval.tick().await;
+            reporter.publish();
+            match &bars {
+                Some(bars) => bars.update(&reporter.snapshot()),
+                None => reporter.report(),
+            }
+        }
+        reporter.publish();
+        if let Some(bars) = bars {
+            bars.finish();
+        }
+    })
+}
+
+/// Progress bars of the collections and items read by a search, with their throughput and ETA.
+pub struct SearchBars {
+    collections: ProgressBar,
+    items: ProgressBar,
+}
+
+impl SearchBars {
+    /// Adds the bars to the shared progress bars. `None` if those are not drawn, when stderr is
+    /// not a terminal or progress is reported as JSON.
+    pub fn new() -> Option<Self> {
+        let bars = progress::bars();
+        if bars.is_hidden() {
+            return None;
+        }
+        let bar = |unit: &str| {
+            let bar = bars.add(ProgressBar::new(0));
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(&format!(
+                        "{{spinner:.green}} [{{bar:40.green/white}}] {{pos}}/{{len}} {} ({{per_sec}}, {{eta}})",
+                        unit
+                    ))
+                    .unwrap()
+                    .progress_chars("#>-"),
+            );
+            bar
+        };
+        Some(SearchBars {
+            collections: bar("collections"),
+            items: bar("items"),
+        })
+    }
+
+    pub fn update(&self, progress: &SearchProgress) {
+        self.collections
+            .set_length(progress.collections_total as u64);
+        self.collections
+            .set_position(progress.collections_read as u64);
+        self.items.set_length(progress.items_total as u64);
+        self.items.set_position(progress.items_read as u64);
+    }
+
+    pub fn finish(&self) {
+        self.collections.finish_and_clear();
+        self.items.finish_and_clear();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,7 +233,7 @@ mod tests {
         assert_eq!(reporter.urls_total.load(Ordering::Relaxed), 0);
         assert_eq!(reporter.collections_read.load(Ordering::Relaxed), 0);
         assert_eq!(reporter.open_threads.load(Ordering::Relaxed), 0);
-        assert_eq!(reporter.collections_total, collections_total);
+        assert_eq!(reporter.snapshot().collections_total, collections_total);
         assert!(!reporter.stop_flag.load(Ordering::Relaxed));
     }
 
@@ -153,9 +293,42 @@ mod tests {
         assert_eq!(reporter.open_threads.load(Ordering::Relaxed), 0);
     }
 
+    #[tokio::test]
+    async fn test_spawn_reporting() {
+        let reporter = Arc::new(Reporter::new(2));
+        let mut progress = reporter.subscribe();
+        let reporting = spawn_reporting(reporter.clone());
+
+        reporter.add_urls(3);
+        reporter.add_thread();
+        reporter.report_finished_url();
+        progress
+            .wait_for(|progress| progress.items_read == 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            *progress.borrow(),
+            SearchProgress {
+                collections_read: 0,
+                collections_total: 2,
+                items_read: 1,
+                items_total: 3,
+                active_workers: 1,
+            }
+        );
+
+        reporter.report_finished_thread();
+        reporter.report_finished_collection();
+        reporter.stop_flag.store(true, Ordering::Relaxed);
+        reporting.await.unwrap();
+        assert_eq!(progress.borrow().collections_read, 1);
+        assert_eq!(progress.borrow().active_workers, 0);
+    }
+
     #[test]
     fn test_reset_all() {
-        let mut reporter = Reporter::new(5);
+        let reporter = Reporter::new(5);
+        reporter.stop_flag.store(true, Ordering::Relaxed);
         reporter.add_urls(10);
         reporter.report_finished_url();
         reporter.report_finished_collection();
@@ -163,6 +336,7 @@ mod tests {
         assert_eq!(reporter.urls_read.load(Ordering::Relaxed), 0);
         assert_eq!(reporter.urls_total.load(Ordering::Relaxed), 0);
         assert_eq!(reporter.collections_read.load(Ordering::Relaxed), 0);
-        assert_eq!(reporter.collections_total, 3);
+        assert_eq!(reporter.snapshot().collections_total, 3);
+        assert!(!reporter.stop_flag.load(Ordering::Relaxed));
     }
 }
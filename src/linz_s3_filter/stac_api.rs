@@ -0,0 +1,430 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, info};
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use stac::{Collection, Item};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::{DatasetMatch, MatchingItems};
+use crate::linz_s3_filter::fetch::FetchPolicy;
+use crate::linz_s3_filter::utils::{get_hrefs, with_self_href};
+use crate::progress;
+
+/// Number of items asked for per page of search results.
+const PAGE_LIMIT: usize = 250;
+
+/// A STAC API server, queried through its `/collections` and `/search` endpoints
+/// instead of crawling a static catalog.
+#[derive(Clone, Debug)]
+pub struct StacApi {
+    pub url: String,
+    client: Client,
+    policy: FetchPolicy,
+}
+
+/// Parameters of an item search, sent as the JSON body of `POST /search`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ItemSearch {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bbox: Option<[f64; 4]>,
+    /// A date, or an interval such as "2020-01-01T00:00:00Z/..".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datetime: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ItemPage {
+    #[serde(default)]
+    features: Vec<Item>,
+    #[serde(default)]
+    links: Vec<PageLink>,
+}
+
+#[derive(Deserialize)]
+struct CollectionPage {
+    #[serde(default)]
+    collections: Vec<Collection>,
+    #[serde(default)]
+    links: Vec<PageLink>,
+}
+
+/// A pagination link. For `POST` searches the next page is requested with `body`,
+/// merged into the previous body if `merge` is set.
+#[derive(Deserialize)]
+struct PageLink {
+    rel: String,
+    href: String,
+    method: Option<String>,
+    body: Option<Map<String, Value>>,
+    #[serde(default)]
+    merge: bool,
+}
+
+/// Parses a JSON response, counting its size in the run statistics.
+async fn read_json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, MyError> {
+    let body = response.error_for_status()?.bytes().await?;
+    progress::stats().add_bytes(body.len() as u64);
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// The `datetime` of a search between `start` and `end`, open ended where `None`.
+fn datetime_interval(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Option<String> {
+    if start.is_none() && end.is_none() {
+        return None;
+    }
+    let format = |date: Option<DateTime<Utc>>| {
+        date.map_or("..".to_string(), |date| {
+            date.to_rfc3339_opts(SecondsFormat::Secs, true)
+        })
+    };
+    Some(format!("{}/{}", format(start), format(end)))
+}
+
+fn next_link(links: Vec<PageLink>) -> Option<PageLink> {
+    links.into_iter().find(|link| link.rel == "next")
+}
+
+impl StacApi {
+    pub fn new(url: &str) -> Self {
+        StacApi {
+            url: url.trim_end_matches('/').to_string(),
+            client: Client::new(),
+            policy: FetchPolicy::default(),
+        }
+    }
+
+    /// Sets the timeout and retries of each request.
+    pub fn with_policy(mut self, policy: FetchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// All collections of the server, following `next` links.
+    pub async fn collections(&self) -> Result<Vec<Collection>, MyError> {
+        let mut collections = vec![];
+        let mut url = format!("{}/collections", self.url);
+        loop {
+            let page: CollectionPage = self
+                .policy
+                .run(&url, || async {
+                    read_json(self.client.get(&url).send().await?).await
+                })
+                .await?;
+            collections.extend(page.collections);
+            match next_link(page.links) {
+                Some(link) => url = link.href,
+                None => return Ok(collections),
+            }
+        }
+    }
+
+    /// All items matching a search, following `next` links page by page.
+    ///
+    /// If `cancel` is cancelled, the items of the pages fetched so far are returned.
+    pub async fn search(
+        &self,
+        search: &ItemSearch,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Item>, MyError> {
+        let mut items = vec![];
+        let mut url = format!("{}/search", self.url);
+        let mut method = "POST".to_string();
+        let Value::Object(mut body) = serde_json::to_value(search)? else {
+            unreachable!("searches serialize to objects");
+        };
+        loop {
+            let fetch = self.policy.run(&url, || async {
+                let request = if method.eq_ignore_ascii_case("GET") {
+                    self.client.get(&url)
+                } else {
+                    self.client.post(&url).json(&body)
+                };
+                read_json(request.send().await?).await
+            });
+            let page: ItemPage = tokio::select! {
+                page = fetch => page?,
+                _ = cancel.cancelled() => return Ok(items),
+            };
+            debug!("{} items in page of {}", page.features.len(), url);
+            let empty = page.features.is_empty();
+            items.extend(page.features.into_iter().map(with_self_href));
+            let Some(link) = next_link(page.links).filter(|_| !empty) else {
+                return Ok(items);
+            };
+            url = link.href;
+            method = link.method.unwrap_or_else(|| "GET".to_string());
+            match link.body {
+                Some(next) if link.merge => body.extend(next),
+                Some(next) => body = next,
+                None => {}
+            }
+        }
+    }
+
+    /// Searches the items of `collections` within `bbox` and between `start` and `end`, and
+    /// groups them into datasets, in the same form as a crawl of a static catalog.
+    ///
+    /// A cancelled search keeps the items of the pages fetched before it.
+    pub async fn get_tiles(
+        &self,
+        collections: &[Collection],
+        bbox: Option<[f64; 4]>,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<DatasetMatch>, MyError> {
+        if collections.is_empty() {
+            return Ok(vec![]);
+        }
+        let search = ItemSearch {
+            bbox,
+            datetime: datetime_interval(start, end),
+            collections: collections
+                .iter()
+                .map(|collection| collection.id.clone())
+                .collect(),
+            limit: Some(PAGE_LIMIT),
+        };
+        let items = self.search(&search, cancel).await?;
+        info!("{} items found by {}", items.len(), self.url);
+        let mut results: Vec<MatchingItems> = collections
+            .iter()
+            .map(|collection| MatchingItems {
+                title: collection.title.clone().unwrap_or_default(),
+                collection: collection.clone(),
+                items: vec![],
+            })
+            .collect();
+        for item in items {
+            let result = results
+                .iter_mut()
+                .find(|result| item.collection.as_ref() == Some(&result.collection.id));
+            match result {
+                Some(result) => result.items.push(item),
+                None => debug!("Item {} is not in a searched collection", item.id),
+            }
+        }
+        results.retain(|result| !result.items.is_empty());
+        Ok(get_hrefs(results).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serves the responses made from its own URL in order, one per connection, and records the requests.
+    async fn serve(
+        responses: impl FnOnce(&str) -> Vec<Value>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = responses(&url);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0; 4096];
+                // Read the headers, then as much body as Content-Length says
+                loop {
+                    let n = socket.read(&mut buffer).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((headers, body)) = text.split_once("\r\n\r\n") {
+                        let length = headers
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())?
+                            })
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_string());
+                let body = response.to_string();
+                let reply = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn item(id: &str, collection: &str) -> Value {
+        json!({
+            "type": "Feature",
+            "stac_version": "1.0.0",
+            "id": id,
+            "collection": collection,
+            "geometry": null,
+            "bbox": [170.0, -46.0, 171.0, -45.0],
+            "properties": {"datetime": "2020-01-01T00:00:00Z"},
+            "links": [{"rel": "self", "href": format!("https://example.com/{}/{}.json", collection, id)}],
+            "assets": {"data": {"href": format!("./{}.tif", id), "file:size": 10}},
+        })
+    }
+
+    #[test]
+    fn test_datetime_interval() {
+        let date = |date: &str| Some(date.parse().unwrap());
+        assert_eq!(datetime_interval(None, None), None);
+        assert_eq!(
+            datetime_interval(date("2020-01-01T00:00:00Z"), date("2021-06-30T12:00:00Z")).unwrap(),
+            "2020-01-01T00:00:00Z/2021-06-30T12:00:00Z"
+        );
+        assert_eq!(
+            datetime_interval(None, date("2021-06-30T12:00:00Z")).unwrap(),
+            "../2021-06-30T12:00:00Z"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_tiles() {
+        let (url, requests) = serve(|url| {
+            vec![
+                json!({
+                    "type": "FeatureCollection",
+                    "features": [item("a", "first"), item("b", "second")],
+                    "links": [{"rel": "next", "href": format!("{}/search", url), "method": "POST", "body": {"token": "page2"}, "merge": true}],
+                }),
+                json!({
+                    "type": "FeatureCollection",
+                    "features": [item("c", "first")],
+                    "links": [],
+                }),
+            ]
+        })
+        .await;
+        let api = StacApi::new(&format!("{}/", url));
+        let mut first = Collection::new("first", "a description");
+        first.title = Some("First 1m DEM".to_string());
+        let mut second = Collection::new("second", "a description");
+        second.title = Some("Second 8m DEM".to_string());
+        let unmatched = Collection::new("third", "a description");
+
+        let datasets = api
+            .get_tiles(
+                &[second, first, unmatched],
+                Some([170.0, -46.0, 171.0, -45.0]),
+                Some("2020-01-01T00:00:00Z".parse().unwrap()),
+                None,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        let titles: Vec<_> = datasets
+            .iter()
+            .map(|dataset| dataset.title.as_str())
+            .collect();
+        assert_eq!(titles, ["First 1m DEM", "Second 8m DEM"]);
+        let hrefs: Vec<_> = datasets[0]
+            .tiles
+            .iter()
+            .map(|tile| tile.href.as_str())
+            .collect();
+        assert_eq!(
+            hrefs,
+            [
+                "https://example.com/first/a.tif",
+                "https://example.com/first/c.tif"
+            ]
+        );
+        assert_eq!(datasets[0].tiles[0].size, Some(10));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        for request in requests.iter() {
+            assert!(request.starts_with("POST /search "));
+            assert!(request.contains("\"bbox\":[170.0,-46.0,171.0,-45.0]"));
+            assert!(request.contains("\"collections\":[\"second\",\"first\",\"third\"]"));
+            assert!(request.contains("\"datetime\":\"2020-01-01T00:00:00Z/..\""));
+        }
+        assert!(!requests[0].contains("page2"));
+        assert!(requests[1].contains("\"token\":\"page2\""));
+    }
+
+    #[tokio::test]
+    async fn test_get_tiles_cancelled() {
+        // Only the first page is served, so the request for the second one waits out its backoff
+        let (url, _) = serve(|url| {
+            vec![json!({
+                "type": "FeatureCollection",
+                "features": [item("a", "first")],
+                "links": [{"rel": "next", "href": format!("{}/search?page=2", url)}],
+            })]
+        })
+        .await;
+        let api = StacApi::new(&url).with_policy(FetchPolicy {
+            backoff: std::time::Duration::from_secs(60),
+            ..FetchPolicy::default()
+        });
+        let first = Collection::new("first", "a description");
+        let cancel = CancellationToken::new();
+        let timer = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            timer.cancel();
+        });
+
+        let datasets = api
+            .get_tiles(&[first], None, None, None, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(datasets.len(), 1);
+        assert_eq!(datasets[0].tiles[0].href, "https://example.com/first/a.tif");
+    }
+
+    #[tokio::test]
+    async fn test_collections() {
+        let (url, requests) = serve(|url| {
+            let collection = |id: &str| {
+                let mut collection = Collection::new(id, "a description");
+                collection.title = Some(id.to_uppercase());
+                serde_json::to_value(collection).unwrap()
+            };
+            vec![
+                json!({
+                    "collections": [collection("first")],
+                    "links": [{"rel": "next", "href": format!("{}/collections?page=2", url)}],
+                }),
+                json!({"collections": [collection("second")], "links": []}),
+            ]
+        })
+        .await;
+        let collections = StacApi::new(&url).collections().await.unwrap();
+        let ids: Vec<_> = collections
+            .iter()
+            .map(|collection| collection.id.as_str())
+            .collect();
+        assert_eq!(ids, ["first", "second"]);
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("GET /collections "));
+        assert!(requests[1].starts_with("GET /collections?page=2 "));
+    }
+}
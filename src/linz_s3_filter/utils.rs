@@ -1,9 +1,14 @@
+use crate::progress;
 use crate::{error::MyError, linz_s3_filter::linz_bucket::CollectionTaskContext};
 
-use super::dataset::MatchingItems;
+use super::dataset::{DatasetMatch, MatchingItems, Tile};
+use super::item_index::ItemIndex;
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
 use log::debug;
 use regex::Regex;
-use stac::{Assets, Collection, Links, SelfHref};
+use serde_json::Value;
+use stac::{Assets, Collection, Href, Item, Links, SelfHref};
 
 pub fn get_coordinate_from_dimension(
     lat: f64,
@@ -23,71 +28,162 @@ pub fn get_coordinate_from_dimension(
     (lat1, lon1, lat2, lon2)
 }
 
-pub async fn get_hrefs(results: Vec<MatchingItems>) -> Vec<(Vec<String>, String)> {
-    let mut hrefs_with_titles = Vec::new();
-    for result in results {
-        let mut items = vec![];
-        for item in result.items {
-            let assets = item.assets();
-            for value in assets.values() {
-                let asset_href = value.href.to_string();
-                let absolute_path = if asset_href.starts_with("./") {
-                    let href = item.self_href().unwrap().to_string();
-                    let base_path = href.rsplit_once('/').map(|x| x.0).unwrap_or("");
-                    format!("{}/{}", base_path, asset_href.strip_prefix("./").unwrap())
-                } else {
-                    asset_href
-                };
-                items.push(absolute_path); // Push the absolute path instead of the original href
-            }
-        }
-        hrefs_with_titles.push((items, result.title));
+/// First start and last end date of a collection's temporal extent.
+pub fn collection_date_range(
+    collection: &Collection,
+) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let intervals = &collection.extent.temporal.interval;
+    let start = intervals.iter().filter_map(|interval| interval[0]).min();
+    let end = intervals.iter().filter_map(|interval| interval[1]).max();
+    (start, end)
+}
+
+/// Number of items a collection links to, without fetching them.
+pub fn item_count(collection: &Collection) -> usize {
+    collection
+        .links()
+        .iter()
+        .filter(|link| link.is_item())
+        .count()
+}
+
+/// One tab separated line describing a collection: id, title, bbox of the spatial extent
+/// as lon_min,lat_min,lon_max,lat_max, temporal extent as start/end and number of items.
+pub fn collection_summary(collection: &Collection) -> String {
+    let bbox = collection
+        .extent
+        .spatial
+        .bbox
+        .first()
+        .map(|bbox| {
+            format!(
+                "{:.4},{:.4},{:.4},{:.4}",
+                bbox.xmin(),
+                bbox.ymin(),
+                bbox.xmax(),
+                bbox.ymax()
+            )
+        })
+        .unwrap_or_default();
+    let (start, end) = collection_date_range(collection);
+    let date = |date: Option<DateTime<Utc>>| {
+        date.map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "..".to_string())
+    };
+    format!(
+        "{}\t{}\t{}\t{}/{}\t{} items",
+        collection.id,
+        collection.title.as_deref().unwrap_or(""),
+        bbox,
+        date(start),
+        date(end),
+        item_count(collection)
+    )
+}
+
+pub async fn get_hrefs(results: Vec<MatchingItems>) -> Vec<DatasetMatch> {
+    let mut hrefs_with_titles: Vec<_> = results.into_iter().map(dataset_match).collect();
+    sort_datasets(&mut hrefs_with_titles);
+    hrefs_with_titles
+}
+
+/// The tiles of the items that matched in a collection.
+pub fn dataset_match(result: MatchingItems) -> DatasetMatch {
+    let tiles = result.items.iter().flat_map(item_tiles).collect();
+    DatasetMatch {
+        title: result.title,
+        collection: result.collection,
+        items: result.items,
+        tiles,
     }
-    hrefs_with_titles.sort_by(|a, b| a.1.cmp(&b.1));
+}
+
+/// Sorts datasets by resolution first, and within each resolution alphabetically.
+pub fn sort_datasets(datasets: &mut [DatasetMatch]) {
+    datasets.sort_by(|a, b| a.title.cmp(&b.title));
 
-    hrefs_with_titles.sort_by(|a, b| {
-        let a_key = extract_value_before_m(&a.1);
-        let b_key = extract_value_before_m(&b.1);
-        a_key.partial_cmp(&b_key).unwrap()
+    datasets.sort_by(|a, b| {
+        let a_key = extract_value_before_m(&a.title);
+        let b_key = extract_value_before_m(&b.title);
+        a_key.total_cmp(&b_key)
     });
-    hrefs_with_titles
 }
 
+/// Tiles for all assets of an item. Relative hrefs are resolved against the item's self href.
+pub fn item_tiles(item: &Item) -> Vec<Tile> {
+    let mut tiles = vec![];
+    for value in item.assets().values() {
+        tiles.push(Tile {
+            href: absolute_href(item, &value.href.to_string()), // Use the absolute path instead of the original href
+            size: value
+                .additional_fields
+                .get("file:size")
+                .and_then(|size| size.as_u64()),
+            item_id: item.id.clone(),
+            item_href: item.self_href().map(|href| href.to_string()),
+            checksum: value
+                .additional_fields
+                .get("file:checksum")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            updated: value
+                .updated
+                .clone()
+                .or_else(|| item.properties.updated.clone()),
+        });
+    }
+    tiles
+}
+
+/// Resolves an href relative to the item's self href. Without a self href it is returned as is.
+pub fn absolute_href(item: &Item, href: &str) -> String {
+    match (href.strip_prefix("./"), item.self_href()) {
+        (Some(relative), Some(self_href)) => {
+            let self_href = self_href.to_string();
+            let base_path = self_href.rsplit_once('/').map(|x| x.0).unwrap_or("");
+            format!("{}/{}", base_path, relative)
+        }
+        (Some(_), None) => {
+            debug!("Item {} has no self href to resolve {}", item.id, href);
+            href.to_string()
+        }
+        (None, _) => href.to_string(),
+    }
+}
+
+/// Sets the self href of an item from its `self` link, for items read from a search or a file
+/// rather than from their own href.
+pub fn with_self_href(mut item: Item) -> Item {
+    if item.self_href().is_none() {
+        let href = item
+            .links()
+            .iter()
+            .find(|link| link.is_self())
+            .map(|link| link.href.to_string());
+        *item.self_href_mut() = href.map(Href::from);
+    }
+    item
+}
+
+/// Searches a collection for items within `bounds`, or all of its items without bounds.
 pub async fn process_collection(
     ctx: CollectionTaskContext,
-    lon1_opt: Option<f64>,
-    lat1_opt: Option<f64>,
-    lon2_opt: Option<f64>,
-    lat2_opt: Option<f64>,
+    bounds: Option<Bounds>,
 ) -> Option<MatchingItems> {
-    if let (Some(lon1), Some(lat1)) = (lon1_opt, lat1_opt) {
-        let (lon_min, lon_max, lat_min, lat_max) =
-            if let (Some(lon2), Some(lat2)) = (lon2_opt, lat2_opt) {
-                (
-                    lon1.min(lon2),
-                    lon1.max(lon2),
-                    lat1.min(lat2),
-                    lat1.max(lat2),
-                )
-            } else {
-                (lon1, lon1, lat1, lat1)
-            };
-
-        for bbox in &ctx.collection.extent.spatial.bbox {
-            if bbox.ymin() <= lat_max
-                && bbox.ymax() >= lat_min
-                && bbox.xmin() <= lon_max
-                && bbox.xmax() >= lon_min
-            {
-                return add_collection_with_spatial_filter(ctx, lon_min, lat_min, lon_max, lat_max)
-                    .await;
-            }
+    let Some((lon_min, lat_min, lon_max, lat_max)) = bounds else {
+        return add_collection(ctx, None).await;
+    };
+    for bbox in &ctx.collection.extent.spatial.bbox {
+        if bbox.ymin() <= lat_max
+            && bbox.ymax() >= lat_min
+            && bbox.xmin() <= lon_max
+            && bbox.xmax() >= lon_min
+        {
+            return add_collection(ctx, bounds).await;
         }
-        ctx.reporter.report_finished_collection();
-        None
-    } else {
-        add_collection_without_filters(ctx).await
     }
+    ctx.reporter.report_finished_collection();
+    None
 }
 
 pub fn extract_value_before_m(text: &str) -> f64 {
@@ -100,126 +196,119 @@ pub fn extract_value_before_m(text: &str) -> f64 {
     }
 }
 
-pub async fn add_collection_with_spatial_filter(
-    ctx: CollectionTaskContext,
+/// Bounds of a spatial filter, as `(lon_min, lat_min, lon_max, lat_max)`.
+pub type Bounds = (f64, f64, f64, f64);
 
-    lon_min: f64,
-    lat_min: f64,
-    lon_max: f64,
-    lat_max: f64,
+/// The items of the collection within `bounds`, or all of them without bounds. Preloaded items
+/// or the item index are used if there are any, otherwise the items are fetched.
+pub async fn add_collection(
+    mut ctx: CollectionTaskContext,
+    bounds: Option<Bounds>,
 ) -> Option<MatchingItems> {
-    let title = ctx.collection.title.clone().unwrap_or_default();
-    let urls = extract_urls(&ctx.collection);
-
-    ctx.reporter.add_urls(urls.len());
-
-    let mut handles = Vec::with_capacity(urls.len());
-    for url in urls {
-        let reporter = ctx.reporter.clone();
-        let semaphore = ctx.semaphore.clone();
-        let store = ctx.store.clone();
-        let handle = tokio::spawn(async move {
-            let permit = semaphore.acquire().await.unwrap();
-
-            reporter.add_thread();
-            debug!("Processing URL: {}", url);
-            let result: Result<stac::Item, stac_io::Error> = store.get(url).await;
-            drop(permit);
-            reporter.report_finished_url();
-            reporter.report_finished_thread();
-
-            match result {
-                Ok(item) => {
-                    let matches = item.bbox.iter().any(|bbox| {
-                        bbox.ymin() <= lat_max
-                            && bbox.ymax() >= lat_min
-                            && bbox.xmin() <= lon_max
-                            && bbox.xmax() >= lon_min
-                    });
-                    if matches {
-                        Some(item)
-                    } else {
-                        None
-                    }
-                }
-                Err(e) => {
-                    MyError::from(e).report();
-                    None
-                }
+    let items = if let Some(items) = ctx.items.take() {
+        progress::stats().add_cache_hit();
+        items
+    } else if let Some(index) = load_index(&ctx) {
+        ctx.reporter.report_finished_collection();
+        let items = match bounds {
+            Some((lon_min, lat_min, lon_max, lat_max)) => {
+                index.items_within(lon_min, lat_min, lon_max, lat_max)
             }
-        });
-        handles.push(handle);
-    }
+            None => index.items(),
+        };
+        return matching_items(ctx.collection, items);
+    } else {
+        let urls = extract_urls(&ctx.collection);
+        let items = fetch_items(&ctx, urls).await;
+        debug!(
+            "Finished processing collection: {}",
+            ctx.collection.title.as_deref().unwrap_or_default()
+        );
+        items
+    };
+    ctx.reporter.report_finished_collection();
 
-    let mut results = Vec::with_capacity(handles.len());
-    for handle in handles {
-        results.push(handle.await);
-    }
+    let items = match bounds {
+        Some(bounds) => items
+            .into_iter()
+            .filter(|item| intersects(item, bounds))
+            .collect(),
+        None => items,
+    };
+    matching_items(ctx.collection, items)
+}
 
-    let matching_items: Vec<_> = results
-        .into_iter()
-        .filter_map(Result::ok)
-        .flatten()
-        .collect();
-    ctx.reporter.report_finished_collection();
-    debug!("Finished processing collection: {}", title);
+fn intersects(item: &Item, (lon_min, lat_min, lon_max, lat_max): Bounds) -> bool {
+    item.bbox.iter().any(|bbox| {
+        bbox.ymin() <= lat_max
+            && bbox.ymax() >= lat_min
+            && bbox.xmin() <= lon_max
+            && bbox.xmax() >= lon_min
+    })
+}
 
-    if !matching_items.is_empty() {
-        Some(MatchingItems {
-            title,
-            items: matching_items,
-        })
-    } else {
-        None
+fn matching_items(collection: Collection, items: Vec<Item>) -> Option<MatchingItems> {
+    if items.is_empty() {
+        return None;
     }
+    Some(MatchingItems {
+        title: collection.title.clone().unwrap_or_default(),
+        collection,
+        items,
+    })
 }
 
-pub async fn add_collection_without_filters(ctx: CollectionTaskContext) -> Option<MatchingItems> {
-    let title = ctx.collection.title.clone().unwrap_or_default();
-    let urls = extract_urls(&ctx.collection);
+/// The item index of the collection, if indexing is enabled and the index is up to date.
+fn load_index(ctx: &CollectionTaskContext) -> Option<ItemIndex> {
+    let index = ItemIndex::load(ctx.index_dir.as_deref()?, &ctx.collection)?;
+    debug!("Using item index of {}", ctx.collection.id);
+    progress::stats().add_cache_hit();
+    Some(index)
+}
 
+/// Fetches the items at `urls`, at most `ctx.workers` at a time, indexing them if all of them
+/// could be read. The fetches of all collections share the permits of `ctx.semaphore`.
+/// Once the search is cancelled, items that have not been read yet are given up.
+async fn fetch_items(ctx: &CollectionTaskContext, urls: Vec<String>) -> Vec<Item> {
     ctx.reporter.add_urls(urls.len());
+    let fetched: Vec<_> = stream::iter(urls)
+        .map(|url| fetch_item(ctx, url))
+        .buffer_unordered(ctx.workers.max(1))
+        .collect()
+        .await;
 
-    let mut handles = Vec::with_capacity(urls.len());
-    for url in urls {
-        let reporter = ctx.reporter.clone();
-        let semaphore = ctx.semaphore.clone();
-        let store = ctx.store.clone();
-        let handle = tokio::spawn(async move {
-            let permit = semaphore.acquire().await.unwrap();
-
-            reporter.add_thread();
-            debug!("Processing URL: {}", url);
-            let result = store.get(url).await;
-            drop(permit);
-            reporter.report_finished_url();
-            reporter.report_finished_thread();
-            result.ok()
-        });
-        handles.push(handle);
-    }
-
-    let mut results = Vec::with_capacity(handles.len());
-    for handle in handles {
-        results.push(handle.await);
+    let complete = fetched.iter().all(Option::is_some);
+    let items: Vec<_> = fetched.into_iter().flatten().collect();
+    if let Some(index_dir) = ctx.index_dir.as_ref().filter(|_| complete) {
+        if let Err(e) = ItemIndex::build(&ctx.collection, items.clone()).write(index_dir) {
+            e.report();
+        }
     }
+    items
+}
 
-    let matching_items: Vec<_> = results
-        .into_iter()
-        .filter_map(Result::ok)
-        .flatten()
-        .collect();
-
-    ctx.reporter.report_finished_collection();
-
-    if !matching_items.is_empty() {
-        Some(MatchingItems {
-            title,
-            items: matching_items,
-        })
-    } else {
-        None
-    }
+/// Fetches one item, `None` if it could not be read or the search was cancelled.
+async fn fetch_item(ctx: &CollectionTaskContext, url: String) -> Option<Item> {
+    let permit = tokio::select! {
+        biased;
+        _ = ctx.cancel.cancelled() => {
+            ctx.reporter.report_finished_url();
+            return None;
+        }
+        permit = ctx.semaphore.acquire() => permit.ok()?,
+    };
+
+    ctx.reporter.add_thread();
+    debug!("Processing URL: {}", url);
+    let result: Option<Result<Item, MyError>> = tokio::select! {
+        result = ctx.fetch_policy.get(&ctx.store, &url) => Some(result),
+        _ = ctx.cancel.cancelled() => None,
+    };
+    drop(permit);
+    ctx.reporter.report_finished_url();
+    ctx.reporter.report_finished_thread();
+
+    result?.map_err(|e| ctx.failures.add_item(&url, &e)).ok()
 }
 
 fn extract_urls(collection: &Collection) -> Vec<String> {
@@ -240,7 +329,6 @@ fn extract_urls(collection: &Collection) -> Vec<String> {
 mod tests {
     use std::path::Path;
 
-    use stac::Item;
     use stac_io::parse_href;
 
     use super::*;
@@ -259,6 +347,53 @@ mod tests {
         assert_eq!(extract_value_before_m("123.45m some text"), 123.45);
         assert_eq!(extract_value_before_m("no number before m"), f64::MAX);
     }
+    #[test]
+    fn test_collection_summary() {
+        let mut collection = Collection::new("an-id", "a description");
+        collection.title = Some("Otago LiDAR 1m DEM (2021)".to_string());
+        collection.extent.spatial.bbox = vec![stac::Bbox::new(168.5, -46.5, 171.0, -44.0)];
+        collection.extent.temporal.interval = vec![[
+            Some("2021-03-01T00:00:00Z".parse().unwrap()),
+            Some("2021-09-30T00:00:00Z".parse().unwrap()),
+        ]];
+        collection.links = vec![
+            stac::Link::new("./a.json", "item"),
+            stac::Link::new("./b.json", "item"),
+            stac::Link::new("../catalog.json", "parent"),
+        ];
+        assert_eq!(item_count(&collection), 2);
+        assert_eq!(
+            collection_summary(&collection),
+            "an-id\tOtago LiDAR 1m DEM (2021)\t168.5000,-46.5000,171.0000,-44.0000\t2021-03-01/2021-09-30\t2 items"
+        );
+
+        collection.extent.temporal.interval = vec![[None, None]];
+        assert!(collection_summary(&collection).contains("\t../..\t"));
+    }
+
+    #[test]
+    fn test_item_tiles() {
+        let item: Item = serde_json::from_value(serde_json::json!({
+            "type": "Feature",
+            "stac_version": "1.0.0",
+            "id": "a",
+            "geometry": null,
+            "properties": {"datetime": "2020-01-01T00:00:00Z", "updated": "2024-01-01T00:00:00Z"},
+            "links": [],
+            "assets": {
+                "dem": {"href": "a.tif", "file:checksum": "1220ab", "updated": "2025-01-01T00:00:00Z"},
+                "hillshade": {"href": "b.tif"},
+            },
+        }))
+        .unwrap();
+        let mut tiles = item_tiles(&item);
+        tiles.sort_by(|a, b| a.href.cmp(&b.href));
+        assert_eq!(tiles[0].checksum.as_deref(), Some("1220ab"));
+        assert_eq!(tiles[0].updated.as_deref(), Some("2025-01-01T00:00:00Z"));
+        assert_eq!(tiles[1].checksum, None);
+        assert_eq!(tiles[1].updated.as_deref(), Some("2024-01-01T00:00:00Z"));
+    }
+
     #[tokio::test]
     async fn test_get_hrefs() {
         use crate::linz_s3_filter::dataset::MatchingItems;
@@ -270,25 +405,29 @@ mod tests {
         let matching_items = vec![
             MatchingItems {
                 title: "10m title".to_string(),
+                collection: Collection::new("10m-id", "a description"),
                 items: vec![item1.clone()],
             },
             MatchingItems {
                 title: "5m title".to_string(),
+                collection: Collection::new("5m-id", "a description"),
                 items: vec![item2.clone()],
             },
         ];
 
         let hrefs = get_hrefs(matching_items).await;
         assert_eq!(hrefs.len(), 2);
-        assert_eq!(hrefs[0].1, "5m title");
-        assert_eq!(hrefs[1].1, "10m title");
+        assert_eq!(hrefs[0].title, "5m title");
+        assert_eq!(hrefs[1].title, "10m title");
     }
     #[tokio::test]
     #[ignore = "Sets race condition while changing current directory"]
     async fn test_process_collection() {
+        use crate::linz_s3_filter::fetch::{FetchFailures, FetchPolicy};
         use crate::linz_s3_filter::reporter::Reporter;
         use std::env;
         use std::sync::Arc;
+        use tokio_util::sync::CancellationToken;
         let original_dir = env::current_dir().expect("Failed to get current directory");
         let new_dir = Path::new("tests/data/");
         env::set_current_dir(new_dir).expect("Failed to change directory");
@@ -304,11 +443,14 @@ mod tests {
                 store,
                 reporter,
                 semaphore,
+                workers: 100,
+                index_dir: None,
+                items: None,
+                cancel: CancellationToken::new(),
+                fetch_policy: FetchPolicy::default(),
+                failures: FetchFailures::default(),
             },
-            Some(172.93),
-            Some(1.35),
-            None,
-            None,
+            Some((172.93, 1.35, 172.93, 1.35)),
         )
         .await;
         assert!(result.is_some());
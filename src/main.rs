@@ -1,131 +1,544 @@
-use clap::Parser;
-use env_logger::Env;
-use linz_s3::args::SpatialFilterParams;
-use linz_s3::linz_s3_filter::bucket_config;
+use clap::{Parser, ValueEnum};
+use env_logger::{Env, Target};
+use futures::StreamExt;
+use indicatif::{HumanBytes, HumanDuration};
+use linz_s3::args::{
+    CacheCommand, ChangesArgs, CollectionFilterArgs, Command, ConfigCommand, DownloadArgs,
+    ExportArgs, InfoArgs, ListArgs, OutputFormat, SearchArgs, SelectionArgs, SpatialFilterParams,
+    TransferArgs,
+};
+use linz_s3::cache::{cached_datasets, verify_dataset, TileProblem};
+use linz_s3::changes::check_for_changes;
+use linz_s3::download::{fill_unknown_sizes, DownloadOptions};
+use linz_s3::error::MyError;
+use linz_s3::export::{write_datasets, write_footprints, ExportFormat, Footprint};
+use linz_s3::info::{fetch_items, AssetStats, CollectionInfo};
+use linz_s3::linz_s3_filter::bucket_config::{self, ConfigFile, CONFIG_FILE};
+use linz_s3::linz_s3_filter::dataset::DatasetMatch;
+use linz_s3::linz_s3_filter::linz_bucket::{LinzBucket, TileQuery};
+use linz_s3::linz_s3_filter::utils::{self, Bounds};
 use linz_s3::process_tile_list;
-use linz_s3::{search_catalog, Cli};
-use log::{error, info};
-use std::io::{self, Write};
+use linz_s3::progress::{self, ProgressFormat};
+use linz_s3::selection::IndexSelection;
+use linz_s3::{collect_datasets, tui, Area, CancellationToken, Cli, SearchRequest};
+use log::{error, info, warn};
+use reqwest::Client;
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 /// Command-line arguments for the LINZ S3 filter tool.
 
 #[tokio::main]
 async fn main() {
-    let args = Cli::parse();
-    env_logger::Builder::from_env(Env::default().default_filter_or(&args.log_level)).init();
-    bucket_config::ConfigFile::init();
-
-    let spatial_filter_params = if args.spatial_filter.is_some() {
-        Some(SpatialFilterParams::new(args.spatial_filter.unwrap()))
+    let cli = Cli::parse();
+    // Log lines go through the progress bars, which would otherwise lose their colors
+    let write_style = if io::stderr().is_terminal() {
+        "always"
     } else {
-        None
+        "never"
+    };
+    let mut logger = env_logger::Builder::from_env(
+        Env::default()
+            .default_filter_or(&cli.log_level)
+            .default_write_style_or(write_style),
+    );
+    logger.target(Target::Pipe(Box::new(progress::LogWriter)));
+    // JSON progress on stderr carries the log lines as events, so stderr is all JSON
+    if cli.progress == ProgressFormat::Json && cli.progress_file.is_none() {
+        logger.format(progress::write_log_event);
+    }
+    logger.init();
+    // The config commands read config.toml themselves, so they can repair a broken one
+    let init_config = match cli.command {
+        Command::Config { .. } => Ok(()),
+        _ => bucket_config::ConfigFile::init(),
+    };
+    if let Err(e) =
+        init_config.and_then(|()| progress::init(cli.progress, cli.progress_file.as_deref()))
+    {
+        e.report();
+        std::process::exit(e.exit_code());
+    }
+    progress::stats();
+
+    let result = match cli.command {
+        Command::Search(args) => run_search(args).await,
+        Command::Download(args) => run_download(args).await,
+        Command::List(args) => run_list(args).await,
+        Command::Info(args) => run_info(args).await,
+        Command::Export(args) => run_export(args).await,
+        Command::Changes(args) => run_changes(args).await,
+        Command::Cache { command } => run_cache(command).await,
+        Command::Config { command } => run_config(command),
     };
-    let cache_path_opt: &Option<PathBuf> = &args
-        .download_args
-        .cache
-        .map(|cache| Path::new(&cache).to_owned());
-    let download = !args.download_args.disable_download;
-    let tile_list = search_catalog(
-        args.bucket,
-        spatial_filter_params,
-        args.include_collection_name,
-        args.exclude_collection_name,
-        args.thread_multiplier,
-    )
-    .await;
-    match tile_list {
-        Ok(tile_list) => {
-            for (index, (tile_paths, description)) in tile_list.iter().enumerate() {
-                let tile_count = tile_paths.len();
+    progress::stats().report();
+    if let Err(e) = result {
+        e.report();
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn download_options(
+    transfer: TransferArgs,
+    download: bool,
+    tile_index: Option<PathBuf>,
+) -> DownloadOptions {
+    DownloadOptions {
+        download,
+        cache: transfer.cache.map(PathBuf::from),
+        max_bytes: transfer.max_bytes,
+        confirm_above: transfer.confirm_above,
+        assume_yes: transfer.yes,
+        max_downloads: transfer.max_downloads,
+        limit_rate: transfer.limit_rate,
+        sync: transfer.sync,
+        prune: transfer.prune,
+        tile_index,
+        client: Client::new(),
+    }
+}
+
+fn tile_query(filter: CollectionFilterArgs, bounds: Option<Bounds>) -> TileQuery {
+    TileQuery {
+        include: filter.include_collection_name.unwrap_or_default(),
+        exclude: filter.exclude_collection_name.unwrap_or_default(),
+        properties: filter.properties,
+        bounds,
+        ..Default::default()
+    }
+}
+
+/// Indices of the datasets with the given collection ids, in the order of the ids.
+fn indices_by_collection_id(
+    tile_list: &[DatasetMatch],
+    collection_ids: &[String],
+) -> Result<Vec<usize>, MyError> {
+    let mut indices = vec![];
+    for collection_id in collection_ids {
+        match tile_list
+            .iter()
+            .position(|dataset| &dataset.collection.id == collection_id)
+        {
+            Some(index) => {
                 info!(
-                    "{}. {} - Number of Tiles: {}",
-                    index, description, tile_count
+                    "Automatically picked dataset by collection id {}: {}",
+                    collection_id, &tile_list[index].title
                 );
+                indices.push(index);
             }
-            match tile_list.len() {
-                0 => {
-                    info!("No datasets found.");
-                    return;
-                }
-                1 => {
-                    info!("Exactly 1 dataset found, processing...");
-                    process_tile_list(&tile_list, 0, download, cache_path_opt).await;
-                }
-                _ => {
-                    info!("{} datasets found.", tile_list.len());
-                    if args.by_first_index || args.by_index.is_some() {
-                        let index = args.by_index.unwrap_or(0); // if none then by_first_index is set
+            None => {
+                return Err(MyError::InvalidInput(format!(
+                    "Collection id {} is not among the datasets found.",
+                    collection_id
+                )))
+            }
+        }
+    }
+    Ok(indices)
+}
 
-                        if index < tile_list.len() {
+async fn run_search(args: SearchArgs) -> Result<(), MyError> {
+    let spatial_filter_params = args.spatial_filter.map(SpatialFilterParams::new);
+    let aoi = spatial_filter_params.as_ref().map(|params| params.bounds());
+    let download_options = download_options(args.transfer, !args.disable_download, args.tile_index);
+    let mut request = SearchRequest::new(args.catalog.bucket)
+        .with_collections(
+            args.collection_filter
+                .include_collection_name
+                .unwrap_or_default(),
+        )
+        .without_collections(
+            args.collection_filter
+                .exclude_collection_name
+                .unwrap_or_default(),
+        )
+        .with_properties(args.collection_filter.properties)
+        .with_strict(args.catalog.strict);
+    if let Some(params) = spatial_filter_params {
+        request = request.with_area(Area::try_from(params)?);
+    }
+    if let Some(multiplier) = args.catalog.thread_multiplier {
+        request = request.with_concurrency(multiplier);
+    }
+    let search = CancellationToken::new();
+    let ctrl_c = tokio::spawn(cancel_on_ctrl_c(search.clone()));
+    let mut datasets = request.stream(search.clone()).await?;
+    let mut tile_list = vec![];
+    while let Some(result) = datasets.next().await {
+        let dataset = match result {
+            Ok(dataset) => dataset,
+            Err(e) if args.catalog.strict => return Err(e),
+            Err(e) => {
+                e.report();
+                continue;
+            }
+        };
+        info!(
+            "Found {} ({}) - Number of Tiles: {}",
+            dataset.title,
+            dataset.collection.id,
+            dataset.tiles.len()
+        );
+        tile_list.push(dataset);
+    }
+    if search.is_cancelled() {
+        warn!(
+            "Search canceled, {} datasets found so far. They may be missing tiles.",
+            tile_list.len()
+        );
+    }
+    // The search is over, so a Ctrl-C from here on exits
+    search.cancel();
+    utils::sort_datasets(&mut tile_list);
+    for (index, dataset) in tile_list.iter().enumerate() {
+        let tile_count = dataset.tiles.len();
+        info!(
+            "{}. {} ({}) - Number of Tiles: {}, Size: {}",
+            index,
+            dataset.title,
+            dataset.collection.id,
+            tile_count,
+            dataset.size_label()
+        );
+    }
+    let Some(indices) = select_datasets(&tile_list, &args.selection, aoi)? else {
+        return Ok(());
+    };
+    if download_options.download {
+        for &index in &indices {
+            fill_unknown_sizes(&download_options.client, &mut tile_list[index].tiles).await;
+        }
+    }
+    if let Some(path) = &args.export {
+        let datasets: Vec<_> = indices.iter().map(|&index| &tile_list[index]).collect();
+        let count = write_datasets(path, &datasets)?;
+        info!("Wrote {} items to {}", count, path.display());
+    }
+    ctrl_c.abort();
+    process_tile_list(&tile_list, &indices, &download_options).await
+}
+
+/// Cancels the search on the first Ctrl-C, so the datasets found so far can still be picked.
+/// Once the search is cancelled or finished, a Ctrl-C exits as it would without a handler.
+async fn cancel_on_ctrl_c(search: CancellationToken) {
+    while tokio::signal::ctrl_c().await.is_ok() {
+        if search.is_cancelled() {
+            std::process::exit(130);
+        }
+        info!("Canceling search, press Ctrl-C again to exit...");
+        search.cancel();
+    }
+}
+
+/// Picks datasets as set by the selection arguments, or else interactively. `None` if nothing was picked.
+fn select_datasets(
+    tile_list: &[DatasetMatch],
+    selection: &SelectionArgs,
+    aoi: Option<(f64, f64, f64, f64)>,
+) -> Result<Option<Vec<usize>>, MyError> {
+    match tile_list.len() {
+        0 => {
+            info!("No datasets found.");
+            Ok(None)
+        }
+        1 => {
+            info!("Exactly 1 dataset found, processing...");
+            Ok(Some(vec![0]))
+        }
+        _ => {
+            info!("{} datasets found.", tile_list.len());
+            if selection.by_first_index || selection.by_index.is_some() {
+                // if none then by_first_index is set
+                let indices = match &selection.by_index {
+                    Some(selection) => selection.resolve(tile_list.len()),
+                    None => Ok(vec![0]),
+                };
+                match indices {
+                    Ok(indices) => {
+                        for &index in &indices {
                             info!(
                                 "Automatically picked dataset by index {}: {}",
-                                index, &tile_list[index].1
+                                index, &tile_list[index].title
                             );
-
-                            process_tile_list(&tile_list, index, download, cache_path_opt).await;
-                        } else {
-                            eprintln!("Error: Index {} is out of bounds. There are only {} datasets available.", index, tile_list.len());
-                        }
-                    } else if args.by_size {
-                        let index_of_longest = tile_list
-                            .iter()
-                            .enumerate()
-                            .rev()
-                            .max_by_key(|(_, (vec, _))| vec.len())
-                            .map(|(index, _)| index)
-                            .unwrap();
-                        info!(
-                            "Automatically picked dataset with most tiles: {}",
-                            &tile_list[index_of_longest].1
-                        );
-
-                        process_tile_list(&tile_list, index_of_longest, download, cache_path_opt)
-                            .await;
-                    } else if args.by_all {
-                        info!("Automatically picked all datasets.");
-                        for (index, _) in tile_list.iter().enumerate() {
-                            process_tile_list(&tile_list, index, download, cache_path_opt).await;
                         }
-                    } else {
-                        loop {
+                        Ok(Some(indices))
+                    }
+                    Err(index) => Err(MyError::InvalidInput(format!(
+                        "Index {} is out of bounds. There are only {} datasets available.",
+                        index,
+                        tile_list.len()
+                    ))),
+                }
+            } else if let Some(collection_ids) = &selection.collection_id {
+                indices_by_collection_id(tile_list, collection_ids).map(Some)
+            } else if selection.by_size {
+                let index_of_longest = tile_list
+                    .iter()
+                    .enumerate()
+                    .rev()
+                    .max_by_key(|(_, dataset)| dataset.tiles.len())
+                    .map(|(index, _)| index)
+                    .unwrap_or(0);
+                info!(
+                    "Automatically picked dataset with most tiles: {}",
+                    &tile_list[index_of_longest].title
+                );
+                Ok(Some(vec![index_of_longest]))
+            } else if selection.by_all {
+                info!("Automatically picked all datasets.");
+                Ok(Some((0..tile_list.len()).collect()))
+            } else if !selection.no_tui && io::stdout().is_terminal() && io::stdin().is_terminal() {
+                match tui::select_datasets(tile_list, aoi) {
+                    Ok(Some(indices)) => {
+                        for &index in &indices {
                             info!(
-                                "Please choose a dataset (enter index or type 'cancel' to exit):"
+                                "You picked dataset number {}: {}",
+                                index, &tile_list[index].title
                             );
-                            info!("> ");
-                            io::stdout().flush().unwrap();
+                        }
+                        Ok(Some(indices))
+                    }
+                    Ok(None) => {
+                        info!("Operation canceled.");
+                        Ok(None)
+                    }
+                    Err(e) => Err(e),
+                }
+            } else {
+                loop {
+                    info!(
+                        "Please choose datasets (enter an index, a list such as 0,2,5-7, or type 'cancel' to exit):"
+                    );
+                    info!("> ");
+                    io::stdout().flush()?;
 
-                            let mut input = String::new();
-                            io::stdin().read_line(&mut input).unwrap();
-                            let input = input.trim();
+                    let mut input = String::new();
+                    if io::stdin().read_line(&mut input)? == 0 {
+                        return Err(MyError::InvalidInput(
+                            "No datasets chosen before the end of input.".to_string(),
+                        ));
+                    }
+                    let input = input.trim();
 
-                            if input.eq_ignore_ascii_case("cancel") {
-                                info!("Operation canceled.");
-                                break;
-                            }
+                    if input.eq_ignore_ascii_case("cancel") {
+                        info!("Operation canceled.");
+                        return Ok(None);
+                    }
 
-                            match input.parse::<usize>() {
-                                Ok(index) if index < tile_list.len() => {
-                                    info!(
-                                        "You picked dataset number {}: {}",
-                                        index, &tile_list[index].1
-                                    );
-
-                                    process_tile_list(&tile_list, index, download, cache_path_opt)
-                                        .await;
-
-                                    break;
-                                }
-                                _ => {
-                                    error!("Invalid index. Please enter a valid number.");
-                                }
+                    match input
+                        .parse::<IndexSelection>()
+                        .map(|selection| selection.resolve(tile_list.len()))
+                    {
+                        Ok(Ok(indices)) => {
+                            for &index in &indices {
+                                info!(
+                                    "You picked dataset number {}: {}",
+                                    index, &tile_list[index].title
+                                );
                             }
+                            return Ok(Some(indices));
+                        }
+                        _ => {
+                            error!("Invalid selection. Please enter valid indices.");
                         }
                     }
                 }
             }
         }
-        Err(e) => {
+    }
+}
+
+async fn run_download(args: DownloadArgs) -> Result<(), MyError> {
+    let download_options = download_options(args.transfer, true, args.tile_index);
+    let mut request = SearchRequest::new(args.catalog.bucket)
+        .with_collections(args.collection_ids.clone())
+        .with_strict(args.catalog.strict);
+    if let Some(filter) = args.spatial_filter {
+        request = request.with_area(Area::try_from(SpatialFilterParams::new(filter))?);
+    }
+    if let Some(multiplier) = args.catalog.thread_multiplier {
+        request = request.with_concurrency(multiplier);
+    }
+    let mut tile_list = request.run().await?;
+    let indices = indices_by_collection_id(&tile_list, &args.collection_ids)?;
+    for &index in &indices {
+        fill_unknown_sizes(&download_options.client, &mut tile_list[index].tiles).await;
+    }
+    process_tile_list(&tile_list, &indices, &download_options).await
+}
+
+async fn run_list(args: ListArgs) -> Result<(), MyError> {
+    let linz_bucket =
+        LinzBucket::initialise_catalog(args.catalog.bucket, args.catalog.thread_multiplier).await?;
+    if args.catalog.strict {
+        linz_bucket.failures.check()?;
+    }
+    let query = tile_query(args.collection_filter, None);
+    let mut collections = linz_bucket.collections_matching(&query);
+    collections.sort_by(|a, b| a.title.cmp(&b.title));
+    for collection in &collections {
+        println!("{}", utils::collection_summary(collection));
+    }
+    info!("{} collections listed.", collections.len());
+    Ok(())
+}
+
+async fn run_info(args: InfoArgs) -> Result<(), MyError> {
+    let linz_bucket =
+        LinzBucket::initialise_catalog(args.catalog.bucket, args.catalog.thread_multiplier).await?;
+    let Some(collection) = linz_bucket.collections.iter().find(|collection| {
+        collection.id == args.collection || collection.title.as_ref() == Some(&args.collection)
+    }) else {
+        return Err(MyError::InvalidInput(format!(
+            "No collection with id or title {}.",
+            args.collection
+        )));
+    };
+    let assets = if args.skip_items {
+        None
+    } else {
+        info!("Reading {} items...", utils::item_count(collection));
+        let items = fetch_items(&linz_bucket, collection, linz_bucket.permits).await;
+        Some(AssetStats::new(&items))
+    };
+    linz_bucket.failures.report();
+    if args.catalog.strict {
+        linz_bucket.failures.check()?;
+    }
+    let collection_info = CollectionInfo::new(collection, assets)?;
+    match args.format {
+        OutputFormat::Text => print!("{}", collection_info),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&collection_info)?),
+    }
+    Ok(())
+}
+
+async fn run_export(args: ExportArgs) -> Result<(), MyError> {
+    let linz_bucket =
+        LinzBucket::initialise_catalog(args.catalog.bucket, args.catalog.thread_multiplier).await?;
+    if args.catalog.strict {
+        linz_bucket.failures.check()?;
+    }
+    let bounds = args
+        .spatial_filter
+        .map(|filter| SpatialFilterParams::new(filter).bounds());
+    let query = tile_query(args.collection_filter, bounds);
+    if ExportFormat::from_path(&args.output) == Some(ExportFormat::GeoParquet) {
+        let datasets =
+            collect_datasets(linz_bucket.get_tiles_stream(&query), args.catalog.strict).await?;
+        let count = write_datasets(&args.output, &datasets.iter().collect::<Vec<_>>())?;
+        info!("Wrote {} items to {}", count, args.output.display());
+        return Ok(());
+    }
+    let footprints: Vec<Footprint> = linz_bucket
+        .collections_matching(&query)
+        .into_iter()
+        .filter_map(Footprint::from_collection)
+        .collect();
+    write_footprints(&args.output, &footprints)?;
+    info!(
+        "Wrote {} collection extents to {}",
+        footprints.len(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+async fn run_changes(args: ChangesArgs) -> Result<(), MyError> {
+    let aoi = args
+        .spatial_filter
+        .map(|filter| SpatialFilterParams::new(filter).bounds());
+    let bucket = args.catalog.bucket;
+    let snapshot = args.snapshot.unwrap_or_else(|| {
+        let name = bucket.to_possible_value().unwrap();
+        PathBuf::from(format!("linz_s3_{}_snapshot.json", name.get_name()))
+    });
+    loop {
+        let result = check_for_changes(
+            bucket,
+            &snapshot,
+            aoi,
+            args.catalog.thread_multiplier,
+            args.catalog.strict,
+        )
+        .await;
+        let Some(interval) = args.watch else {
+            return result.map(|_| ());
+        };
+        // A watch keeps going after a failed check
+        if let Err(e) = result {
             e.report();
         }
+        info!("Checking again in {}", HumanDuration(interval));
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+async fn run_cache(command: CacheCommand) -> Result<(), MyError> {
+    match command {
+        CacheCommand::List { dir } => {
+            for dataset in cached_datasets(Path::new(&dir))? {
+                println!(
+                    "{}\t{}\t{} tiles\t{}",
+                    dataset.folder.display(),
+                    dataset.collection_id,
+                    dataset.manifest.tiles.len(),
+                    HumanBytes(dataset.bytes())
+                );
+            }
+        }
+        CacheCommand::Verify { dir } => {
+            let (mut checked, mut failed) = (0, 0);
+            for dataset in cached_datasets(Path::new(&dir))? {
+                checked += dataset.manifest.tiles.len();
+                for (entry, problem) in verify_dataset(&dataset).await? {
+                    failed += 1;
+                    let kind = match problem {
+                        TileProblem::Missing => "missing",
+                        TileProblem::ChecksumMismatch => "changed",
+                    };
+                    println!("{}\t{}", kind, entry.path_in(&dataset.folder).display());
+                }
+            }
+            info!(
+                "Checked {} tiles, {} missing or changed since download.",
+                checked, failed
+            );
+            if failed > 0 {
+                return Err(MyError::CacheMismatch { failed, checked });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_config(command: ConfigCommand) -> Result<(), MyError> {
+    match command {
+        ConfigCommand::Show => {
+            if !Path::new(CONFIG_FILE).exists() {
+                info!("No {} found, showing the defaults.", CONFIG_FILE);
+            }
+            let config = ConfigFile::load().unwrap_or_else(|e| {
+                warn!(
+                    "{} could not be read, showing the defaults. Run \"config init --force\" to replace it. {}",
+                    CONFIG_FILE, e
+                );
+                ConfigFile::default()
+            });
+            print!("{}", config.to_toml()?);
+        }
+        ConfigCommand::Init { force } => {
+            if Path::new(CONFIG_FILE).exists() && !force {
+                return Err(MyError::InvalidInput(format!(
+                    "{} already exists. Use --force to overwrite it.",
+                    CONFIG_FILE
+                )));
+            }
+            std::fs::write(CONFIG_FILE, ConfigFile::default().to_toml()?)?;
+            info!("Wrote the default configuration to {}", CONFIG_FILE);
+        }
     }
+    Ok(())
 }
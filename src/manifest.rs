@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use crate::error::MyError;
+
+pub const MANIFEST_JSON: &str = "manifest.json";
+pub const MANIFEST_CSV: &str = "manifest.csv";
+
+/// Record of one downloaded tile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub source_url: String,
+    pub local_path: String,
+    pub size: u64,
+    /// SHA-256 multihash of the local file in hex, comparable to STAC `file:checksum`.
+    pub checksum: String,
+    pub item_id: String,
+    /// Href of the STAC item JSON, used to find tiles removed from the collection.
+    #[serde(default)]
+    pub item_href: Option<String>,
+    /// `updated` timestamp of the asset at download time, if STAC provided one.
+    #[serde(default)]
+    pub updated: Option<String>,
+    pub collection_id: String,
+    pub collection_title: String,
+    pub license: String,
+    /// RFC 3339 timestamp of the download.
+    pub downloaded_at: String,
+}
+
+impl ManifestEntry {
+    /// Where the tile is in `folder`, wherever the folder was when it was downloaded.
+    pub fn path_in(&self, folder: &Path) -> PathBuf {
+        folder.join(Path::new(&self.local_path).file_name().unwrap_or_default())
+    }
+}
+
+/// The tiles downloaded into one dataset folder, stored as `manifest.json` and `manifest.csv`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub tiles: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Reads the manifest of a dataset folder. A missing manifest is read as empty.
+    pub fn load(folder: &Path) -> Result<Self, MyError> {
+        match std::fs::read(folder.join(MANIFEST_JSON)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn get(&self, local_path: &str) -> Option<&ManifestEntry> {
+        self.tiles
+            .iter()
+            .find(|entry| entry.local_path == local_path)
+    }
+
+    /// Adds an entry, replacing any previous entry for the same local path.
+    pub fn upsert(&mut self, entry: ManifestEntry) {
+        match self
+            .tiles
+            .iter_mut()
+            .find(|existing| existing.local_path == entry.local_path)
+        {
+            Some(existing) => *existing = entry,
+            None => self.tiles.push(entry),
+        }
+    }
+
+    /// Writes the manifest as JSON and CSV into a dataset folder.
+    pub fn write(&self, folder: &Path) -> Result<(), MyError> {
+        std::fs::write(folder.join(MANIFEST_JSON), serde_json::to_vec_pretty(self)?)?;
+        let mut writer = csv::Writer::from_path(folder.join(MANIFEST_CSV))?;
+        for entry in &self.tiles {
+            writer.serialize(entry)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Incrementally computes the size and checksum of a file while it is written.
+#[derive(Default)]
+pub struct ChecksumWriter {
+    hasher: Sha256,
+    size: u64,
+}
+
+impl ChecksumWriter {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.size += chunk.len() as u64;
+    }
+
+    /// Size in bytes and SHA-256 multihash in hex.
+    pub fn finish(self) -> (u64, String) {
+        let digest = self.hasher.finalize();
+        let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        // 0x12 is the multihash code of sha2-256, 0x20 its length in bytes.
+        (self.size, format!("1220{}", hex))
+    }
+}
+
+/// Size and SHA-256 multihash of an existing file.
+pub async fn file_checksum(path: &Path) -> Result<(u64, String), MyError> {
+    let mut file = File::open(path).await?;
+    let mut checksum = ChecksumWriter::default();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        checksum.update(&buffer[..read]);
+    }
+    Ok(checksum.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn entry(local_path: &str, size: u64) -> ManifestEntry {
+        ManifestEntry {
+            source_url: format!("https://example.com/{}", local_path),
+            local_path: local_path.to_string(),
+            size,
+            checksum: "1220ab".to_string(),
+            item_id: "item".to_string(),
+            item_href: None,
+            updated: None,
+            collection_id: "collection".to_string(),
+            collection_title: "Collection".to_string(),
+            license: "CC-BY-4.0".to_string(),
+            downloaded_at: "2025-06-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_checksum_is_sha256_multihash() {
+        let mut checksum = ChecksumWriter::default();
+        checksum.update(b"hello ");
+        checksum.update(b"world");
+        assert_eq!(
+            checksum.finish(),
+            (
+                11,
+                "1220b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let dir = tempdir().unwrap();
+        let mut manifest = Manifest::load(dir.path()).unwrap();
+        assert!(manifest.tiles.is_empty());
+        manifest.upsert(entry("a.tif", 1));
+        manifest.upsert(entry("b.tif", 2));
+        manifest.upsert(entry("a.tif", 3));
+        manifest.write(dir.path()).unwrap();
+
+        let loaded = Manifest::load(dir.path()).unwrap();
+        assert_eq!(loaded.tiles.len(), 2);
+        assert_eq!(loaded.get("a.tif").unwrap().size, 3);
+        let csv = std::fs::read_to_string(dir.path().join(MANIFEST_CSV)).unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert!(csv.starts_with("source_url,local_path,size,checksum,item_id"));
+    }
+}
@@ -0,0 +1,288 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressDrawTarget};
+use log::info;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::error::MyError;
+
+/// How progress is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Log lines and progress bars.
+    #[default]
+    Human,
+    /// Newline-delimited JSON events, for programs wrapping this tool.
+    Json,
+}
+
+/// A progress event, written as one JSON object per line with its name in `event`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent<'a> {
+    /// Collections and items of a search read so far.
+    Search {
+        collections_read: usize,
+        collections_total: usize,
+        items_read: usize,
+        items_total: usize,
+    },
+    /// A dataset matched the search.
+    Match {
+        collection_id: &'a str,
+        title: &'a str,
+        tiles: usize,
+        /// Sum of the known tile sizes.
+        bytes: u64,
+    },
+    /// Bytes and files downloaded so far.
+    Download {
+        bytes_downloaded: u64,
+        bytes_total: u64,
+        files_done: usize,
+        files_total: usize,
+    },
+    /// A tile finished downloading, or failed to.
+    FileDone {
+        url: &'a str,
+        path: &'a str,
+        bytes: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The command finished, see `RunStats`.
+    Summary {
+        elapsed_secs: f64,
+        requests: usize,
+        bytes: u64,
+        cache_hits: usize,
+    },
+    /// A log line, when the events share stderr with the log.
+    Log {
+        level: &'a str,
+        target: &'a str,
+        message: String,
+    },
+}
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+
+/// Sets up JSON progress events, written to `path` or else to stderr. Without this, or with
+/// `ProgressFormat::Human`, events are dropped.
+pub fn init(format: ProgressFormat, path: Option<&Path>) -> Result<(), MyError> {
+    if format == ProgressFormat::Human {
+        return Ok(());
+    }
+    let sink: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stderr()),
+    };
+    let _ = SINK.set(Mutex::new(sink));
+    Ok(())
+}
+
+/// Whether progress is reported as JSON events rather than log lines and progress bars.
+pub fn is_json() -> bool {
+    SINK.get().is_some()
+}
+
+/// Writes an event, if JSON progress is enabled.
+pub fn emit(event: &ProgressEvent) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    if let Ok(line) = event_line(event) {
+        let mut sink = sink.lock().unwrap();
+        // Progress is best effort, a closed pipe must not fail the search or download.
+        let _ = writeln!(sink, "{}", line).and_then(|()| sink.flush());
+    }
+}
+
+/// The progress bars of the search and the downloads, drawn together so they don't overwrite
+/// each other. Hidden when progress is reported as JSON.
+pub fn bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(|| {
+        let bars = MultiProgress::new();
+        if is_json() {
+            bars.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        bars
+    })
+}
+
+/// Formats a log record as a `log` event, so log lines and events sharing stderr can all be
+/// read as JSON.
+pub fn write_log_event(buf: &mut impl Write, record: &log::Record) -> io::Result<()> {
+    let event = ProgressEvent::Log {
+        level: record.level().as_str(),
+        target: record.target(),
+        message: record.args().to_string(),
+    };
+    let line = event_line(&event).map_err(io::Error::other)?;
+    writeln!(buf, "{}", line)
+}
+
+/// Writes log lines to stderr above the progress bars, instead of through them.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        bars().suspend(|| io::stderr().write_all(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Work done by the command so far, for the summary at the end of the run.
+pub struct RunStats {
+    started: Instant,
+    requests: AtomicUsize,
+    bytes: AtomicU64,
+    cache_hits: AtomicUsize,
+}
+
+static STATS: LazyLock<RunStats> = LazyLock::new(|| RunStats {
+    started: Instant::now(),
+    requests: AtomicUsize::new(0),
+    bytes: AtomicU64::new(0),
+    cache_hits: AtomicUsize::new(0),
+});
+
+/// The statistics of this run, started on first use.
+pub fn stats() -> &'static RunStats {
+    &STATS
+}
+
+impl RunStats {
+    /// An HTTP request was sent, retries included.
+    pub fn add_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Bytes received, of STAC API responses or downloaded tiles. Documents read from the
+    /// store are only parsed objects by the time they arrive, and are not counted.
+    pub fn add_bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// A collection was searched from its item index or preloaded items, or a tile was
+    /// already downloaded, instead of being fetched.
+    pub fn add_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> ProgressEvent<'static> {
+        ProgressEvent::Summary {
+            elapsed_secs: self.started.elapsed().as_secs_f64(),
+            requests: self.requests.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Logs or emits the summary of the run, unless nothing was fetched or read from the cache.
+    pub fn report(&self) {
+        let (requests, cache_hits) = (
+            self.requests.load(Ordering::Relaxed),
+            self.cache_hits.load(Ordering::Relaxed),
+        );
+        if requests == 0 && cache_hits == 0 {
+            return;
+        }
+        if is_json() {
+            emit(&self.summary());
+        } else {
+            info!(
+                "Finished in {}: {} requests, {} fetched, {} cache hits",
+                HumanDuration(self.started.elapsed()),
+                requests,
+                HumanBytes(self.bytes.load(Ordering::Relaxed)),
+                cache_hits
+            );
+        }
+    }
+}
+
+fn event_line(event: &ProgressEvent) -> Result<String, MyError> {
+    let mut value = serde_json::to_value(event)?;
+    if let Some(object) = value.as_object_mut() {
+        object.insert("time".to_string(), Utc::now().to_rfc3339().into());
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_event_line() {
+        let line = event_line(&ProgressEvent::FileDone {
+            url: "https://example.com/a.tif",
+            path: "a.tif",
+            bytes: Some(10),
+            error: None,
+        })
+        .unwrap();
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "file_done");
+        assert_eq!(value["bytes"], 10);
+        assert!(value.get("error").is_none());
+        assert!(value["time"].is_string());
+
+        let line = event_line(&ProgressEvent::Search {
+            collections_read: 1,
+            collections_total: 2,
+            items_read: 3,
+            items_total: 4,
+        })
+        .unwrap();
+        assert!(!line.contains('\n'));
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["event"], "search");
+        assert_eq!(value["items_total"], 4);
+
+        let mut buf = vec![];
+        let record = log::Record::builder()
+            .level(log::Level::Warn)
+            .target("linz_s3")
+            .args(format_args!("a \"quoted\" message"))
+            .build();
+        write_log_event(&mut buf, &record).unwrap();
+        let value: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(value["event"], "log");
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["message"], "a \"quoted\" message");
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let stats = RunStats {
+            started: Instant::now(),
+            requests: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            cache_hits: AtomicUsize::new(0),
+        };
+        stats.add_request();
+        stats.add_request();
+        stats.add_bytes(100);
+        stats.add_cache_hit();
+        let value: Value = serde_json::from_str(&event_line(&stats.summary()).unwrap()).unwrap();
+        assert_eq!(value["event"], "summary");
+        assert_eq!(value["requests"], 2);
+        assert_eq!(value["bytes"], 100);
+        assert_eq!(value["cache_hits"], 1);
+        assert!(value["elapsed_secs"].is_f64());
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Limits the combined throughput of all downloads sharing it to a number of bytes per second.
+pub struct RateLimiter {
+    bytes_per_second: f64,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Bytes that may be consumed right away. Negative when callers are ahead of the limit.
+    available: f64,
+    last_update: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1) as f64;
+        RateLimiter {
+            bytes_per_second,
+            state: Mutex::new(State {
+                available: bytes_per_second,
+                last_update: Instant::now(),
+            }),
+        }
+    }
+
+    /// Accounts for `bytes` just received, sleeping as long as needed to stay under the limit.
+    /// Allows a burst of at most one second worth of bytes.
+    pub async fn consume(&self, bytes: u64) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let refill =
+                now.duration_since(state.last_update).as_secs_f64() * self.bytes_per_second;
+            state.available = (state.available + refill).min(self.bytes_per_second);
+            state.last_update = now;
+            state.available -= bytes as f64;
+            if state.available < 0.0 {
+                Duration::from_secs_f64(-state.available / self.bytes_per_second)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_burst_is_not_delayed() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        limiter.consume(1000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_throughput_is_limited() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        limiter.consume(1000).await;
+        limiter.consume(500).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
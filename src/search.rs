@@ -1,67 +1,657 @@
+use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt};
+use log::info;
+use reqwest::Client;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio_util::sync::CancellationToken;
+
+use crate::download::{fill_unknown_sizes, process_tile_list, DownloadOptions};
 use crate::error::MyError;
-use crate::linz_s3_filter::{dataset, linz_bucket::LinzBucket, utils};
+use crate::export::{write_datasets, ExportFormat};
+use crate::linz_s3_filter::{
+    dataset,
+    dataset::{BucketName, DatasetMatch, PropertyFilter},
+    linz_bucket::{LinzBucket, TileQuery},
+    utils,
+    utils::Bounds,
+};
+use crate::tile_index;
 
 use crate::args::SpatialFilterParams;
 
 // use pyo3::prelude::*;
 
+/// Searches a bucket and returns the matching datasets, sorted.
+#[deprecated(note = "build a `SearchRequest` and call `run` instead")]
 pub async fn search_catalog(
     bucket: dataset::BucketName,
     spatial_params: Option<SpatialFilterParams>,
     collection_name_filter_opt: Option<Vec<String>>,
     collection_exclusion_opt: Option<Vec<String>>,
     concurrency_multiplier: Option<usize>,
-) -> Result<Vec<(Vec<String>, String)>, MyError> {
-    let mut linz_bucket = LinzBucket::initialise_catalog(bucket, concurrency_multiplier).await?;
-
-    if let Some(SpatialFilterParams {
-        lat1: lat,
-        lon1: lon,
-        lat2_opt,
-        lon2_opt,
-        width_m_opt,
-        height_m_opt,
-    }) = spatial_params
-    {
-        if (height_m_opt.is_some() || width_m_opt.is_some())
-            && (lat2_opt.is_some() || lon2_opt.is_some())
+) -> Result<Vec<DatasetMatch>, MyError> {
+    positional_request(
+        bucket,
+        spatial_params,
+        collection_name_filter_opt,
+        collection_exclusion_opt,
+        concurrency_multiplier,
+    )?
+    .run()
+    .await
+}
+
+/// Collects the datasets of a search stream, sorted. Errors fail the search with `strict`,
+/// otherwise they are reported and the search carries on.
+pub async fn collect_datasets(
+    mut stream: BoxStream<'static, Result<DatasetMatch, MyError>>,
+    strict: bool,
+) -> Result<Vec<DatasetMatch>, MyError> {
+    let mut tiles = vec![];
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(dataset) => tiles.push(dataset),
+            Err(e) if strict => return Err(e),
+            Err(e) => e.report(),
+        }
+    }
+    utils::sort_datasets(&mut tiles);
+    Ok(tiles)
+}
+
+/// Like `search_catalog`, but yields each matching dataset as soon as its collection has been
+/// searched. Cancelling `cancel` stops the search, see `SearchRequest::stream`.
+#[deprecated(note = "build a `SearchRequest` and call `stream` instead")]
+pub async fn search_stream(
+    bucket: dataset::BucketName,
+    spatial_params: Option<SpatialFilterParams>,
+    collection_name_filter_opt: Option<Vec<String>>,
+    collection_exclusion_opt: Option<Vec<String>>,
+    concurrency_multiplier: Option<usize>,
+    cancel: CancellationToken,
+) -> Result<BoxStream<'static, Result<DatasetMatch, MyError>>, MyError> {
+    positional_request(
+        bucket,
+        spatial_params,
+        collection_name_filter_opt,
+        collection_exclusion_opt,
+        concurrency_multiplier,
+    )?
+    .stream(cancel)
+    .await
+}
+
+/// The `SearchRequest` of the arguments of the positional search functions.
+fn positional_request(
+    bucket: dataset::BucketName,
+    spatial_params: Option<SpatialFilterParams>,
+    collection_name_filter_opt: Option<Vec<String>>,
+    collection_exclusion_opt: Option<Vec<String>>,
+    concurrency_multiplier: Option<usize>,
+) -> Result<SearchRequest, MyError> {
+    let mut request = SearchRequest::new(bucket)
+        .with_collections(collection_name_filter_opt.unwrap_or_default())
+        .without_collections(collection_exclusion_opt.unwrap_or_default());
+    if let Some(params) = spatial_params {
+        request = request.with_area(Area::try_from(params)?);
+    }
+    if let Some(multiplier) = concurrency_multiplier {
+        request = request.with_concurrency(multiplier);
+    }
+    Ok(request)
+}
+
+/// Where a search reads the catalog from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum SearchSource {
+    /// A LINZ bucket, crawled or searched through the STAC API set for it in `config.toml`.
+    Bucket(BucketName),
+    /// A STAC API server, by its root URL.
+    StacApi(String),
+}
+
+impl From<BucketName> for SearchSource {
+    fn from(bucket: BucketName) -> Self {
+        SearchSource::Bucket(bucket)
+    }
+}
+
+/// The area a search looks for tiles in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Area {
+    /// Tiles containing a point.
+    Point { lat: f64, lon: f64 },
+    /// Tiles intersecting the box between two corners, in any order.
+    Bbox {
+        lat1: f64,
+        lon1: f64,
+        lat2: f64,
+        lon2: f64,
+    },
+    /// Tiles intersecting a box of about `width_m` by `height_m` metres centred on a point.
+    /// The box is square without `height_m`.
+    Around {
+        lat: f64,
+        lon: f64,
+        width_m: f64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        height_m: Option<f64>,
+    },
+}
+
+impl Area {
+    /// Bounding box of the area as (lon_min, lat_min, lon_max, lat_max).
+    pub fn bounds(&self) -> Bounds {
+        let (lat1, lon1, lat2, lon2) = match *self {
+            Area::Point { lat, lon } => (lat, lon, lat, lon),
+            Area::Bbox {
+                lat1,
+                lon1,
+                lat2,
+                lon2,
+            } => (lat1, lon1, lat2, lon2),
+            Area::Around {
+                lat,
+                lon,
+                width_m,
+                height_m,
+            } => {
+                utils::get_coordinate_from_dimension(lat, lon, width_m, height_m.unwrap_or(width_m))
+            }
+        };
+        (
+            lon1.min(lon2),
+            lat1.min(lat2),
+            lon1.max(lon2),
+            lat1.max(lat2),
+        )
+    }
+
+    fn validate(&self) -> Result<(), MyError> {
+        let (points, sizes) = match *self {
+            Area::Point { lat, lon } => (vec![(lat, lon)], vec![]),
+            Area::Bbox {
+                lat1,
+                lon1,
+                lat2,
+                lon2,
+            } => (vec![(lat1, lon1), (lat2, lon2)], vec![]),
+            Area::Around {
+                lat,
+                lon,
+                width_m,
+                height_m,
+            } => (
+                vec![(lat, lon)],
+                [Some(width_m), height_m].into_iter().flatten().collect(),
+            ),
+        };
+        for (lat, lon) in points {
+            if !(-90.0..=90.0).contains(&lat) {
+                return Err(invalid(format!("latitude {} is not within -90 to 90", lat)));
+            }
+            if !(-180.0..=180.0).contains(&lon) {
+                return Err(invalid(format!(
+                    "longitude {} is not within -180 to 180",
+                    lon
+                )));
+            }
+        }
+        match sizes
+            .into_iter()
+            .find(|size| !size.is_finite() || *size <= 0.0)
         {
+            Some(size) => Err(invalid(format!("area size {} m is not positive", size))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl TryFrom<SpatialFilterParams> for Area {
+    type Error = MyError;
+
+    fn try_from(params: SpatialFilterParams) -> Result<Self, Self::Error> {
+        let SpatialFilterParams {
+            lat1,
+            lon1,
+            lat2_opt,
+            lon2_opt,
+            width_m_opt,
+            height_m_opt,
+        } = params;
+        let range = lat2_opt.is_some() || lon2_opt.is_some();
+        if (width_m_opt.is_some() || height_m_opt.is_some()) && range {
             return Err(MyError::DimensionAndCoordinateRange);
         }
+        Ok(match width_m_opt {
+            Some(width_m) => Area::Around {
+                lat: lat1,
+                lon: lon1,
+                width_m,
+                height_m: height_m_opt,
+            },
+            None if height_m_opt.is_some() => {
+                return Err(invalid("area height without a width".to_string()))
+            }
+            None if range => Area::Bbox {
+                lat1,
+                lon1,
+                lat2: lat2_opt.unwrap_or(lat1),
+                lon2: lon2_opt.unwrap_or(lon1),
+            },
+            None => Area::Point {
+                lat: lat1,
+                lon: lon1,
+            },
+        })
+    }
+}
+
+/// Where the item indexes of crawled collections are kept.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum IndexCache {
+    /// The `index_dir` of `config.toml`.
+    #[default]
+    Config,
+    /// This directory.
+    Dir(PathBuf),
+    /// No indexes, items are always fetched.
+    Disabled,
+}
+
+/// Files a search writes for the datasets it finds, besides returning them.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SearchOutput {
+    /// Writes the datasets to this file, in the format of its extension: item footprints as
+    /// GeoJSON (`.geojson`, `.json`), FlatGeobuf (`.fgb`) or KML (`.kml`), or whole items as
+    /// stac-geoparquet (`.parquet`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub export: Option<PathBuf>,
+    /// Writes the tiles of the datasets to this GeoPackage, one layer per dataset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tile_index: Option<PathBuf>,
+    /// Downloads the tiles of the datasets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download: Option<DownloadSettings>,
+}
+
+impl SearchOutput {
+    fn is_empty(&self) -> bool {
+        self == &SearchOutput::default()
+    }
+}
+
+/// Where and how a search downloads the tiles it finds. Downloads never ask for confirmation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[non_exhaustive]
+pub struct DownloadSettings {
+    /// Directory to download into, one folder per dataset. The current directory if `None`.
+    pub folder: Option<PathBuf>,
+    /// Refuse to download more than this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Maximum number of files downloaded at the same time.
+    pub max_downloads: usize,
+    /// Combined download rate limit in bytes per second.
+    pub limit_rate: Option<u64>,
+    /// Download tiles again if they changed upstream, instead of skipping every existing file.
+    pub sync: bool,
+    /// When syncing, delete local tiles that are no longer part of their collection.
+    pub prune: bool,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            folder: None,
+            max_bytes: None,
+            max_downloads: 8,
+            limit_rate: None,
+            sync: false,
+            prune: false,
+        }
+    }
+}
+
+impl DownloadSettings {
+    pub fn new(folder: impl Into<PathBuf>) -> Self {
+        DownloadSettings {
+            folder: Some(folder.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_downloads(mut self, max_downloads: usize) -> Self {
+        self.max_downloads = max_downloads;
+        self
+    }
+
+    pub fn with_limit_rate(mut self, bytes_per_second: u64) -> Self {
+        self.limit_rate = Some(bytes_per_second);
+        self
+    }
+
+    /// Downloads changed tiles again, and with `prune` deletes the ones no longer listed.
+    pub fn with_sync(mut self, prune: bool) -> Self {
+        self.sync = true;
+        self.prune = prune;
+        self
+    }
+
+    fn options(&self, tile_index: Option<PathBuf>) -> DownloadOptions {
+        DownloadOptions {
+            download: true,
+            cache: self.folder.clone(),
+            max_bytes: self.max_bytes,
+            confirm_above: u64::MAX,
+            assume_yes: true,
+            max_downloads: self.max_downloads,
+            limit_rate: self.limit_rate,
+            sync: self.sync,
+            prune: self.prune,
+            tile_index,
+            client: Client::new(),
+        }
+    }
+}
 
-        let (lat1_opt, lon1_opt, lat2_opt, lon2_opt) = if let Some(width_m) = width_m_opt {
-            let height_m = height_m_opt.unwrap_or(width_m);
+/// A search of a bucket, built step by step and checked by `validate` before it runs:
+///
+/// ```no_run
+/// # async fn example() -> Result<(), linz_s3::error::MyError> {
+/// use linz_s3::linz_s3_filter::dataset::BucketName;
+/// use linz_s3::search::{Area, SearchRequest};
+///
+/// let datasets = SearchRequest::new(BucketName::Elevation)
+///     .with_area(Area::Point { lat: -45.0, lon: 167.0 })
+///     .with_collections(["DEM"])
+///     .run()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Requests can also be read from and written as JSON, with the field names below.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct SearchRequest {
+    pub source: SearchSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub area: Option<Area>,
+    /// Only collections with one of these in their id or title.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Leaves out collections with one of these in their id or title.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Collection fields to match, as `key=value`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<PropertyFilter>,
+    /// Only collections whose temporal extent ends at or after this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start: Option<DateTime<Utc>>,
+    /// Only collections whose temporal extent starts at or before this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<DateTime<Utc>>,
+    /// Concurrent requests per CPU, 1 by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_multiplier: Option<usize>,
+    #[serde(default)]
+    pub index_cache: IndexCache,
+    /// Fail with `MyError::IncompleteSearch` if any collection or item could not be read,
+    /// instead of returning what was found.
+    #[serde(default)]
+    pub strict: bool,
+    /// Files `run` writes for the datasets found.
+    #[serde(default, skip_serializing_if = "SearchOutput::is_empty")]
+    pub output: SearchOutput,
+}
 
-            let (lat1, lon1, lat2, lon2) =
-                utils::get_coordinate_from_dimension(lat, lon, width_m, height_m);
-            (Some(lat1), Some(lon1), Some(lat2), Some(lon2))
-        } else {
-            (Some(lat), Some(lon), lat2_opt, lon2_opt)
+impl SearchRequest {
+    pub fn new(source: impl Into<SearchSource>) -> Self {
+        SearchRequest {
+            source: source.into(),
+            area: None,
+            include: vec![],
+            exclude: vec![],
+            properties: vec![],
+            start: None,
+            end: None,
+            concurrency_multiplier: None,
+            index_cache: IndexCache::default(),
+            strict: false,
+            output: SearchOutput::default(),
+        }
+    }
+
+    pub fn with_area(mut self, area: Area) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    pub fn with_collections(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.include.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn without_collections(
+        mut self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.exclude.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_properties(mut self, filters: impl IntoIterator<Item = PropertyFilter>) -> Self {
+        self.properties.extend(filters);
+        self
+    }
+
+    /// Only collections with data between `start` and `end`, either open ended if `None`.
+    pub fn with_dates(mut self, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Self {
+        self.start = start;
+        self.end = end;
+        self
+    }
+
+    pub fn with_concurrency(mut self, multiplier: usize) -> Self {
+        self.concurrency_multiplier = Some(multiplier);
+        self
+    }
+
+    pub fn with_index_cache(mut self, index_cache: IndexCache) -> Self {
+        self.index_cache = index_cache;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Writes the datasets found to `path`, see `SearchOutput::export` for the formats.
+    pub fn with_export(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output.export = Some(path.into());
+        self
+    }
+
+    pub fn with_tile_index(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output.tile_index = Some(path.into());
+        self
+    }
+
+    pub fn with_download(mut self, settings: DownloadSettings) -> Self {
+        self.output.download = Some(settings);
+        self
+    }
+
+    /// Checks the request can run: coordinates in range, positive sizes, dates in order, a
+    /// usable source and at least one filter, so the whole bucket is not searched by accident.
+    pub fn validate(&self) -> Result<(), MyError> {
+        if let SearchSource::StacApi(url) = &self.source {
+            let parsed =
+                Url::parse(url).map_err(|e| invalid(format!("STAC API URL {}: {}", url, e)))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(invalid(format!("STAC API URL {} is not http(s)", url)));
+            }
+        }
+        if let Some(area) = &self.area {
+            area.validate()?;
+        }
+        if let Some(name) = self
+            .include
+            .iter()
+            .chain(&self.exclude)
+            .find(|name| name.trim().is_empty())
+        {
+            return Err(invalid(format!("empty collection name {:?}", name)));
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start > end {
+                return Err(invalid(format!("start {} is after end {}", start, end)));
+            }
+        }
+        if self.concurrency_multiplier == Some(0) {
+            return Err(invalid("concurrency multiplier of 0".to_string()));
+        }
+        if let Some(path) = &self.output.export {
+            if ExportFormat::from_path(path).is_none() {
+                return Err(invalid(format!(
+                    "unknown export format of {}",
+                    path.display()
+                )));
+            }
+        }
+        if let Some(download) = &self.output.download {
+            if download.max_downloads == 0 {
+                return Err(invalid("max downloads of 0".to_string()));
+            }
+            if download.prune && !download.sync {
+                return Err(invalid("prune without sync".to_string()));
+            }
+        }
+        if self.area.is_none()
+            && self.include.is_empty()
+            && self.properties.is_empty()
+            && self.start.is_none()
+            && self.end.is_none()
+        {
+            return Err(MyError::NoFilterProvided);
+        }
+        Ok(())
+    }
+
+    /// The filters of the request, to search a bucket opened with `open`.
+    pub fn query(&self) -> TileQuery {
+        TileQuery {
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            properties: self.properties.clone(),
+            bounds: self.area.as_ref().map(Area::bounds),
+            start: self.start,
+            end: self.end,
+            ..Default::default()
+        }
+    }
+
+    /// Reads the catalog of the source, stopping with `MyError::Cancelled` when `cancel` is
+    /// cancelled. The bucket can serve further searches, see `LinzBucket::get_tiles_stream`.
+    pub async fn open(&self, cancel: CancellationToken) -> Result<LinzBucket, MyError> {
+        self.validate()?;
+        let mut bucket = match &self.source {
+            SearchSource::Bucket(bucket) => {
+                LinzBucket::initialise_catalog_with_cancel(
+                    *bucket,
+                    self.concurrency_multiplier,
+                    cancel,
+                )
+                .await?
+            }
+            SearchSource::StacApi(url) => {
+                LinzBucket::initialise_stac_api(url, self.concurrency_multiplier, cancel).await?
+            }
         };
+        match &self.index_cache {
+            IndexCache::Config => {}
+            IndexCache::Dir(dir) => bucket.index_dir = Some(dir.clone()),
+            IndexCache::Disabled => bucket.index_dir = None,
+        }
+        Ok(bucket)
+    }
 
-        linz_bucket.set_collection_filter(
-            collection_name_filter_opt.as_deref(),
-            collection_exclusion_opt.as_deref(),
-            Some((lat1_opt.unwrap(), lon1_opt.unwrap(), lat2_opt, lon2_opt)),
-        );
-        let tiles = linz_bucket
-            .get_tiles(lat1_opt, lon1_opt, lat2_opt, lon2_opt)
-            .await;
-
-        Ok(tiles)
-        // Use lat1, lon1, lat2_opt, lon2_opt, width_m_opt, height_m_opt here
-    } else if collection_name_filter_opt.is_some() {
-        linz_bucket.set_collection_filter(
-            collection_name_filter_opt.as_deref(),
-            collection_exclusion_opt.as_deref(),
-            None,
-        );
-        let tiles = linz_bucket.get_all_tiles().await;
-        Ok(tiles)
-    } else {
-        Err(MyError::NoFilterProvided)
+    /// Runs the search, yielding each matching dataset as soon as its collection has been
+    /// searched, unsorted.
+    ///
+    /// Cancelling `cancel` stops the search: collections stop fetching items, the stream yields
+    /// the matches among the items read so far and then ends. Collections and items that could
+    /// not be read are logged when the search ends, and the stream then yields
+    /// `MyError::IncompleteSearch`.
+    pub async fn stream(
+        &self,
+        cancel: CancellationToken,
+    ) -> Result<BoxStream<'static, Result<DatasetMatch, MyError>>, MyError> {
+        let bucket = self.open(cancel.clone()).await?;
+        let query = TileQuery {
+            cancel,
+            ..self.query()
+        };
+        Ok(bucket.get_tiles_stream(&query))
+    }
+
+    /// Runs the search, writes the files of `output` and returns the matching datasets, sorted.
+    pub async fn run(&self) -> Result<Vec<DatasetMatch>, MyError> {
+        let stream = self.stream(CancellationToken::new()).await?;
+        let mut datasets = collect_datasets(stream, self.strict).await?;
+        self.write_output(&mut datasets).await?;
+        Ok(datasets)
+    }
+
+    /// Exports, indexes and downloads `datasets` as set by `output`. Unknown tile sizes are
+    /// filled in before downloading.
+    pub async fn write_output(&self, datasets: &mut [DatasetMatch]) -> Result<(), MyError> {
+        let output = &self.output;
+        if let Some(path) = &output.export {
+            let count = write_datasets(path, &datasets.iter().collect::<Vec<_>>())?;
+            info!("Wrote {} items to {}", count, path.display());
+        }
+        let indices: Vec<usize> = (0..datasets.len()).collect();
+        match &output.download {
+            Some(settings) => {
+                let options = settings.options(output.tile_index.clone());
+                for dataset in datasets.iter_mut() {
+                    fill_unknown_sizes(&options.client, &mut dataset.tiles).await;
+                }
+                process_tile_list(datasets, &indices, &options).await?;
+            }
+            None => {
+                if let Some(path) = &output.tile_index {
+                    tile_index::write_tile_index(path, datasets, &indices, None)?;
+                }
+            }
+        }
+        Ok(())
     }
 }
+
+fn invalid(message: String) -> MyError {
+    MyError::InvalidRequest(message)
+}
 //needs asuync pyo3?
 // #[pyfunction]
 // fn get_tiles(lat: f64, lon: f64) -> PyResult<Vec<(Vec<String>, String)>> {
@@ -73,3 +663,160 @@ pub async fn search_catalog(
 //     m.add_function(wrap_pyfunction!(get_tiles, m)?)?;
 //     Ok(())
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> SearchRequest {
+        SearchRequest::new(BucketName::Elevation).with_area(Area::Point {
+            lat: -45.0,
+            lon: 167.0,
+        })
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(request().validate().is_ok());
+        assert!(matches!(
+            SearchRequest::new(BucketName::Elevation)
+                .without_collections(["DSM"])
+                .validate(),
+            Err(MyError::NoFilterProvided)
+        ));
+        assert!(SearchRequest::new(BucketName::Imagery)
+            .with_collections(["Otago"])
+            .validate()
+            .is_ok());
+
+        let invalid = [
+            request().with_area(Area::Point {
+                lat: -91.0,
+                lon: 167.0,
+            }),
+            request().with_area(Area::Bbox {
+                lat1: -45.0,
+                lon1: 167.0,
+                lat2: -46.0,
+                lon2: f64::NAN,
+            }),
+            request().with_area(Area::Around {
+                lat: -45.0,
+                lon: 167.0,
+                width_m: 100.0,
+                height_m: Some(0.0),
+            }),
+            request().with_collections([" "]),
+            request().with_dates(
+                Some("2022-01-01T00:00:00Z".parse().unwrap()),
+                Some("2021-01-01T00:00:00Z".parse().unwrap()),
+            ),
+            request().with_concurrency(0),
+            request().with_export("datasets.txt"),
+            request().with_download(DownloadSettings::default().with_max_downloads(0)),
+            request().with_download(DownloadSettings {
+                prune: true,
+                ..Default::default()
+            }),
+            SearchRequest {
+                source: SearchSource::StacApi("ftp://example.com".to_string()),
+                ..request()
+            },
+        ];
+        for request in invalid {
+            let result = request.validate();
+            assert!(
+                matches!(result, Err(MyError::InvalidRequest(_))),
+                "{:?}",
+                request
+            );
+        }
+    }
+
+    #[test]
+    fn test_area() {
+        let area = Area::Bbox {
+            lat1: -45.0,
+            lon1: 170.0,
+            lat2: -46.0,
+            lon2: 169.0,
+        };
+        assert_eq!(area.bounds(), (169.0, -46.0, 170.0, -45.0));
+        let params = |lat2_opt, width_m_opt| SpatialFilterParams {
+            lat1: -45.0,
+            lon1: 170.0,
+            lat2_opt,
+            lon2_opt: None,
+            width_m_opt,
+            height_m_opt: None,
+        };
+        assert_eq!(
+            Area::try_from(params(None, None)).unwrap(),
+            Area::Point {
+                lat: -45.0,
+                lon: 170.0
+            }
+        );
+        assert!(matches!(
+            Area::try_from(params(Some(-46.0), None)),
+            Ok(Area::Bbox { lon2: 170.0, .. })
+        ));
+        assert!(matches!(
+            Area::try_from(params(Some(-46.0), Some(100.0))),
+            Err(MyError::DimensionAndCoordinateRange)
+        ));
+    }
+
+    #[test]
+    fn test_serde() {
+        let request = request()
+            .with_collections(["DEM"])
+            .with_properties(["linz:region=otago".parse().unwrap()])
+            .with_dates(Some("2021-01-01T00:00:00Z".parse().unwrap()), None)
+            .with_index_cache(IndexCache::Dir(PathBuf::from("indexes")))
+            .with_strict(true)
+            .with_export("datasets.fgb")
+            .with_download(DownloadSettings::new("tiles").with_sync(false));
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["source"], serde_json::json!({"bucket": "elevation"}));
+        assert_eq!(json["area"]["type"], "point");
+        assert_eq!(json["properties"][0], "linz:region=otago");
+        assert_eq!(json["output"]["export"], "datasets.fgb");
+        assert_eq!(json["output"]["download"]["folder"], "tiles");
+        assert_eq!(json["output"]["download"]["max_downloads"], 8);
+        assert_eq!(
+            serde_json::from_value::<SearchRequest>(json).unwrap(),
+            request
+        );
+
+        let request: SearchRequest = serde_json::from_str(
+            r#"{"source": {"stac_api": "https://example.com/stac"}, "include": ["Otago"]}"#,
+        )
+        .unwrap();
+        assert!(request.validate().is_ok());
+        assert_eq!(request.index_cache, IndexCache::Config);
+        assert_eq!(request.output, SearchOutput::default());
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("output")
+            .is_none());
+        assert!(serde_json::from_str::<SearchRequest>(
+            r#"{"source": {"bucket": "elevation"}, "bounds": [1, 2, 3, 4]}"#
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let (export, tile_index) = (dir.path().join("a.geojson"), dir.path().join("a.gpkg"));
+        request()
+            .with_export(&export)
+            .with_tile_index(&tile_index)
+            .write_output(&mut [])
+            .await
+            .unwrap();
+        assert!(export.exists());
+        assert!(tile_index.exists());
+    }
+}
@@ -0,0 +1,89 @@
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// A selection of dataset indices such as `0,2,5-7`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexSelection(Vec<RangeInclusive<usize>>);
+
+impl IndexSelection {
+    /// Resolves the selection against the number of datasets found, returning sorted, unique indices.
+    /// Fails with the first index that is out of bounds.
+    pub fn resolve(&self, dataset_count: usize) -> Result<Vec<usize>, usize> {
+        let mut indices = Vec::new();
+        for range in &self.0 {
+            if *range.end() >= dataset_count {
+                return Err((*range.start()).max(dataset_count));
+            }
+            indices.extend(range.clone());
+        }
+        indices.sort_unstable();
+        indices.dedup();
+        Ok(indices)
+    }
+}
+
+impl FromStr for IndexSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_index = |part: &str| {
+            part.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid index: {}", part.trim()))
+        };
+        let mut ranges = Vec::new();
+        for part in s.split(',') {
+            if part.trim().is_empty() {
+                return Err(format!("Empty entry in selection: {}", s));
+            }
+            let range = match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse_index(start)?, parse_index(end)?);
+                    if start > end {
+                        return Err(format!("Invalid range: {}", part.trim()));
+                    }
+                    start..=end
+                }
+                None => {
+                    let index = parse_index(part)?;
+                    index..=index
+                }
+            };
+            ranges.push(range);
+        }
+        Ok(IndexSelection(ranges))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list_and_ranges() {
+        let selection: IndexSelection = "0,2,5-7".parse().unwrap();
+        assert_eq!(selection.resolve(10), Ok(vec![0, 2, 5, 6, 7]));
+    }
+
+    #[test]
+    fn test_overlapping_entries_are_deduplicated() {
+        let selection: IndexSelection = " 3-5, 4 ,0".parse().unwrap();
+        assert_eq!(selection.resolve(6), Ok(vec![0, 3, 4, 5]));
+    }
+
+    #[test]
+    fn test_invalid_selections() {
+        assert!("45.5".parse::<IndexSelection>().is_err());
+        assert!("1,,2".parse::<IndexSelection>().is_err());
+        assert!("7-5".parse::<IndexSelection>().is_err());
+        assert!("a-3".parse::<IndexSelection>().is_err());
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let selection: IndexSelection = "1,3-9".parse().unwrap();
+        assert_eq!(selection.resolve(5), Err(5));
+        let selection: IndexSelection = usize::MAX.to_string().parse().unwrap();
+        assert_eq!(selection.resolve(5), Err(usize::MAX));
+    }
+}
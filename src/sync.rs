@@ -0,0 +1,209 @@
+use chrono::DateTime;
+use log::{debug, info, warn};
+use stac::{Collection, Links};
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::Tile;
+use crate::manifest::{file_checksum, Manifest, ManifestEntry};
+
+/// Multihash prefix of SHA-256 checksums, the only kind that can be compared to local files.
+const SHA256_MULTIHASH_PREFIX: &str = "1220";
+
+/// Whether a tile that exists locally has changed upstream since it was downloaded.
+/// Compares the STAC checksum and `updated` timestamp against the manifest entry, or
+/// checksums the local file if the manifest has no entry for it.
+pub async fn is_outdated(tile: &Tile, path: &Path, recorded: Option<&ManifestEntry>) -> bool {
+    let checksum = tile
+        .checksum
+        .as_deref()
+        .filter(|checksum| checksum.starts_with(SHA256_MULTIHASH_PREFIX));
+    match recorded {
+        Some(entry) => {
+            if checksum.is_some_and(|checksum| checksum != entry.checksum) {
+                debug!("Checksum of {} changed upstream", tile.href);
+                return true;
+            }
+            match (&tile.updated, &entry.updated) {
+                (Some(current), Some(recorded)) if is_newer(current, recorded) => {
+                    debug!("{} was updated upstream", tile.href);
+                    true
+                }
+                _ => false,
+            }
+        }
+        None => match checksum {
+            Some(checksum) => match file_checksum(path).await {
+                Ok((_, local)) => local != checksum,
+                Err(e) => {
+                    e.report();
+                    true
+                }
+            },
+            None => false,
+        },
+    }
+}
+
+fn is_newer(current: &str, recorded: &str) -> bool {
+    match (
+        DateTime::parse_from_rfc3339(current),
+        DateTime::parse_from_rfc3339(recorded),
+    ) {
+        (Ok(current), Ok(recorded)) => current > recorded,
+        _ => current != recorded,
+    }
+}
+
+/// Deletes the tiles recorded in a dataset folder's manifest whose item is no longer part of the collection.
+/// Tiles recorded without an item href are kept. Returns the number of tiles removed.
+///
+/// Collections without item links, such as those of a STAC API, are not pruned: their items
+/// cannot be listed, and a search may only have returned some of them.
+pub fn prune_dataset(folder: &Path, collection: &Collection) -> Result<usize, MyError> {
+    let current: HashSet<&str> = collection
+        .links()
+        .iter()
+        .filter(|link| link.is_item())
+        .map(|link| link.href.as_str())
+        .collect();
+    if current.is_empty() {
+        warn!(
+            "Not pruning {}, its collection does not list its items",
+            folder.display()
+        );
+        return Ok(0);
+    }
+    let mut manifest = Manifest::load(folder)?;
+    let (stale, kept): (Vec<_>, Vec<_>) = manifest.tiles.into_iter().partition(|entry| {
+        entry
+            .item_href
+            .as_deref()
+            .is_some_and(|href| !current.contains(href))
+    });
+    for entry in &stale {
+        match std::fs::remove_file(entry.path_in(folder)) {
+            Ok(()) => info!("Removed {}, no longer in the collection", entry.local_path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    manifest.tiles = kept;
+    if !stale.is_empty() {
+        manifest.write(folder)?;
+    }
+    Ok(stale.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn tile(checksum: Option<&str>, updated: Option<&str>) -> Tile {
+        Tile {
+            href: "https://example.com/a.tif".to_string(),
+            size: None,
+            item_id: "a".to_string(),
+            item_href: Some("https://example.com/a.json".to_string()),
+            checksum: checksum.map(str::to_string),
+            updated: updated.map(str::to_string),
+        }
+    }
+
+    fn entry(local_path: &str, item_href: Option<&str>, updated: Option<&str>) -> ManifestEntry {
+        ManifestEntry {
+            source_url: "https://example.com/a.tif".to_string(),
+            local_path: local_path.to_string(),
+            size: 11,
+            checksum: "1220b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+                .to_string(),
+            item_id: "a".to_string(),
+            item_href: item_href.map(str::to_string),
+            updated: updated.map(str::to_string),
+            collection_id: "collection".to_string(),
+            collection_title: "Collection".to_string(),
+            license: "CC-BY-4.0".to_string(),
+            downloaded_at: "2025-06-01T00:00:00+00:00".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_is_outdated_against_manifest() {
+        let path = Path::new("a.tif");
+        let recorded = entry("a.tif", None, Some("2025-01-01T00:00:00Z"));
+        let same = "1220b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        assert!(!is_outdated(&tile(Some(same), None), path, Some(&recorded)).await);
+        assert!(is_outdated(&tile(Some("1220ff"), None), path, Some(&recorded)).await);
+        // Checksums of other hash functions cannot be compared.
+        assert!(!is_outdated(&tile(Some("1114ff"), None), path, Some(&recorded)).await);
+        assert!(
+            is_outdated(
+                &tile(Some(same), Some("2025-02-01T00:00:00Z")),
+                path,
+                Some(&recorded)
+            )
+            .await
+        );
+        assert!(
+            !is_outdated(
+                &tile(Some(same), Some("2025-01-01T00:00:00+00:00")),
+                path,
+                Some(&recorded)
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_is_outdated_without_manifest_checks_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.tif");
+        std::fs::write(&path, "hello world").unwrap();
+        let same = "1220b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+
+        assert!(!is_outdated(&tile(Some(same), None), &path, None).await);
+        assert!(is_outdated(&tile(Some("1220ff"), None), &path, None).await);
+        assert!(!is_outdated(&tile(None, None), &path, None).await);
+    }
+
+    #[test]
+    fn test_prune_dataset() {
+        let dir = tempdir().unwrap();
+        for name in ["kept.tif", "stale.tif", "unknown.tif"] {
+            std::fs::write(dir.path().join(name), "data").unwrap();
+        }
+        let mut manifest = Manifest::default();
+        manifest.upsert(entry(
+            "kept.tif",
+            Some("https://example.com/kept.json"),
+            None,
+        ));
+        manifest.upsert(entry(
+            "stale.tif",
+            Some("https://example.com/stale.json"),
+            None,
+        ));
+        manifest.upsert(entry("unknown.tif", None, None));
+        manifest.write(dir.path()).unwrap();
+
+        let mut collection = Collection::new("collection", "a description");
+        collection
+            .links
+            .push(stac::Link::new("https://example.com/kept.json", "item"));
+
+        assert_eq!(prune_dataset(dir.path(), &collection).unwrap(), 1);
+        assert!(dir.path().join("kept.tif").exists());
+        assert!(!dir.path().join("stale.tif").exists());
+        assert!(dir.path().join("unknown.tif").exists());
+        assert_eq!(Manifest::load(dir.path()).unwrap().tiles.len(), 2);
+
+        // Without item links nothing is known to be stale
+        let collection = Collection::new("collection", "a description");
+        assert_eq!(prune_dataset(dir.path(), &collection).unwrap(), 0);
+        assert!(dir.path().join("kept.tif").exists());
+    }
+}
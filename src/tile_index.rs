@@ -0,0 +1,317 @@
+use geozero::geojson::GeoJsonString;
+use geozero::{CoordDimensions, ToWkb};
+use rusqlite::{params, Connection, Transaction};
+use sanitize_filename::sanitize;
+use serde_json::Value;
+use stac::Item;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::error::MyError;
+use crate::export::{item_date, item_geometry};
+use crate::linz_s3_filter::dataset::DatasetMatch;
+
+/// Spatial reference system of the tile polygons, WGS 84.
+const SRS_ID: i32 = 4326;
+
+const WGS84_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+/// Tables every GeoPackage has, from the GeoPackage 1.3 specification.
+const CORE_TABLES: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT uk_gc_table_name UNIQUE (table_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+";
+
+/// Writes the tiles of the selected datasets to a GeoPackage, one layer per dataset, replacing `path` if it exists.
+///
+/// `output_root` is the directory tiles are downloaded to. A tile gets a local path if its file is there,
+/// in the folder of the first dataset listing it, as in `process_tile_list`.
+pub fn write_tile_index(
+    path: &Path,
+    tile_list: &[DatasetMatch],
+    indices: &[usize],
+    output_root: Option<&Path>,
+) -> Result<(), MyError> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch("PRAGMA application_id = 1196444487; PRAGMA user_version = 10300;")?;
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(CORE_TABLES)?;
+    transaction.execute(
+        "INSERT INTO gpkg_spatial_ref_sys VALUES
+            ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'),
+            ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system'),
+            ('WGS 84 geodetic', ?1, 'EPSG', ?1, ?2, 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid')",
+        params![SRS_ID, WGS84_WKT],
+    )?;
+
+    let mut local_paths: HashMap<&str, PathBuf> = HashMap::new();
+    let mut layer_names = HashSet::new();
+    for &index in indices {
+        let dataset = &tile_list[index];
+        let mut layer_name = dataset.title.clone();
+        let mut count = 1;
+        // Table names are case-insensitive in SQLite
+        while !layer_names.insert(layer_name.to_lowercase()) {
+            count += 1;
+            layer_name = format!("{} ({})", dataset.title, count);
+        }
+        if let Some(output_root) = output_root {
+            let output_folder = output_root.join(sanitize(dataset.title.clone()));
+            for tile in &dataset.tiles {
+                if let Some(file_name) = Path::new(&tile.href).file_name() {
+                    local_paths
+                        .entry(&tile.href)
+                        .or_insert_with(|| output_folder.join(file_name));
+                }
+            }
+        }
+        write_layer(&transaction, &layer_name, dataset, &local_paths)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+fn write_layer(
+    transaction: &Transaction,
+    layer_name: &str,
+    dataset: &DatasetMatch,
+    local_paths: &HashMap<&str, PathBuf>,
+) -> Result<(), MyError> {
+    let table = quote_identifier(layer_name);
+    transaction.execute_batch(&format!(
+        "CREATE TABLE {} (
+            fid INTEGER PRIMARY KEY AUTOINCREMENT,
+            geom GEOMETRY,
+            item_id TEXT,
+            href TEXT NOT NULL,
+            local_path TEXT,
+            size INTEGER,
+            datetime TEXT
+        )",
+        table
+    ))?;
+    let mut insert = transaction.prepare(&format!(
+        "INSERT INTO {} (geom, item_id, href, local_path, size, datetime) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        table
+    ))?;
+    let mut items: HashMap<&str, &Item> = HashMap::new();
+    for item in &dataset.items {
+        items.entry(item.id.as_str()).or_insert(item);
+    }
+    let mut extent: Option<[f64; 4]> = None;
+    for tile in &dataset.tiles {
+        let item = items.get(tile.item_id.as_str()).copied();
+        let geometry = item.and_then(item_geometry);
+        let envelope = geometry.as_ref().and_then(envelope);
+        let geom = match (&geometry, envelope) {
+            (Some(geometry), Some([min_x, min_y, max_x, max_y])) => {
+                extent = Some(match extent {
+                    Some([x0, y0, x1, y1]) => {
+                        [x0.min(min_x), y0.min(min_y), x1.max(max_x), y1.max(max_y)]
+                    }
+                    None => [min_x, min_y, max_x, max_y],
+                });
+                Some(GeoJsonString(geometry.to_string()).to_gpkg_wkb(
+                    CoordDimensions::xy(),
+                    Some(SRS_ID),
+                    vec![min_x, max_x, min_y, max_y],
+                )?)
+            }
+            _ => None,
+        };
+        let local_path = local_paths
+            .get(tile.href.as_str())
+            .filter(|path| path.exists())
+            .map(|path| path.display().to_string());
+        insert.execute(params![
+            geom,
+            tile.item_id,
+            tile.href,
+            local_path,
+            tile.size,
+            item.and_then(item_date),
+        ])?;
+    }
+    let [min_x, min_y, max_x, max_y] = extent.map_or([None; 4], |extent| extent.map(Some));
+    transaction.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, description, min_x, min_y, max_x, max_y, srs_id)
+            VALUES (?1, 'features', ?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![layer_name, dataset.collection.id, min_x, min_y, max_x, max_y, SRS_ID],
+    )?;
+    transaction.execute(
+        "INSERT INTO gpkg_geometry_columns VALUES (?1, 'geom', 'GEOMETRY', ?2, 0, 0)",
+        params![layer_name, SRS_ID],
+    )?;
+    Ok(())
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Bounds of a GeoJSON geometry as `[min_x, min_y, max_x, max_y]`, `None` if it has no coordinates.
+fn envelope(geometry: &Value) -> Option<[f64; 4]> {
+    fn visit(value: &Value, bounds: &mut Option<[f64; 4]>) {
+        let Value::Array(values) = value else {
+            return;
+        };
+        match (
+            values.first().and_then(Value::as_f64),
+            values.get(1).and_then(Value::as_f64),
+        ) {
+            (Some(x), Some(y)) => {
+                let [min_x, min_y, max_x, max_y] = bounds.get_or_insert([x, y, x, y]);
+                *min_x = min_x.min(x);
+                *min_y = min_y.min(y);
+                *max_x = max_x.max(x);
+                *max_y = max_y.max(y);
+            }
+            _ => values.iter().for_each(|value| visit(value, bounds)),
+        }
+    }
+    let mut bounds = None;
+    visit(geometry.get("coordinates")?, &mut bounds);
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linz_s3_filter::dataset::Tile;
+    use serde_json::json;
+    use stac::{Collection, Item};
+    use tempfile::tempdir;
+
+    fn dataset(title: &str, hrefs: &[&str]) -> DatasetMatch {
+        let mut item = Item::new("item");
+        item.geometry = Some(
+            serde_json::from_value(json!({
+                "type": "Polygon",
+                "coordinates": [[[170.0, -46.0], [171.0, -46.0], [171.0, -45.0], [170.0, -46.0]]],
+            }))
+            .unwrap(),
+        );
+        DatasetMatch {
+            title: title.to_string(),
+            collection: Collection::new(title, "a description"),
+            items: vec![item],
+            tiles: hrefs
+                .iter()
+                .map(|href| Tile {
+                    href: href.to_string(),
+                    size: Some(10),
+                    item_id: "item".to_string(),
+                    item_href: None,
+                    checksum: None,
+                    updated: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_write_tile_index() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("First")).unwrap();
+        std::fs::write(dir.path().join("First").join("a.tif"), "tile").unwrap();
+        let tile_list = vec![
+            dataset(
+                "First",
+                &["https://example.com/a.tif", "https://example.com/b.tif"],
+            ),
+            dataset("Second \"quoted\"", &["https://example.com/a.tif"]),
+        ];
+        let path = dir.path().join("index.gpkg");
+        write_tile_index(&path, &tile_list, &[0, 1], Some(dir.path())).unwrap();
+        // Writing again replaces the file
+        write_tile_index(&path, &tile_list, &[0, 1], Some(dir.path())).unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let layers: Vec<(String, f64)> = connection
+            .prepare("SELECT table_name, min_x FROM gpkg_contents ORDER BY table_name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            layers,
+            [
+                ("First".to_string(), 170.0),
+                ("Second \"quoted\"".to_string(), 170.0)
+            ]
+        );
+        let local_paths: Vec<Option<String>> = connection
+            .prepare("SELECT local_path FROM \"First\" ORDER BY fid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected = dir.path().join("First").join("a.tif");
+        assert_eq!(local_paths, [Some(expected.display().to_string()), None]);
+        // The shared tile was downloaded to the folder of the first dataset
+        let local_path: Option<String> = connection
+            .query_row(
+                "SELECT local_path FROM \"Second \"\"quoted\"\"\"",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(local_path, Some(expected.display().to_string()));
+        let geom: Vec<u8> = connection
+            .query_row("SELECT geom FROM \"First\" LIMIT 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(&geom[..2], b"GP");
+
+        // Titles that only differ in case get layers of their own
+        let tile_list = vec![
+            dataset("Otago", &["https://example.com/a.tif"]),
+            dataset("OTAGO", &["https://example.com/b.tif"]),
+        ];
+        write_tile_index(&path, &tile_list, &[0, 1], None).unwrap();
+        let connection = Connection::open(&path).unwrap();
+        let layers: Vec<String> = connection
+            .prepare("SELECT table_name FROM gpkg_contents ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(layers, ["Otago", "OTAGO (2)"]);
+    }
+}
@@ -0,0 +1,470 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::error::MyError;
+use crate::linz_s3_filter::dataset::DatasetMatch;
+
+const HELP: &str =
+    "↑/↓ move  space mark  a mark all  / search  s sort  r reverse  enter confirm  q cancel";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum SortColumn {
+    Resolution,
+    Title,
+    Tiles,
+    Date,
+    Size,
+    Coverage,
+}
+
+impl SortColumn {
+    fn next(self) -> Self {
+        match self {
+            SortColumn::Resolution => SortColumn::Title,
+            SortColumn::Title => SortColumn::Tiles,
+            SortColumn::Tiles => SortColumn::Date,
+            SortColumn::Date => SortColumn::Size,
+            SortColumn::Size => SortColumn::Coverage,
+            SortColumn::Coverage => SortColumn::Resolution,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortColumn::Resolution => "resolution",
+            SortColumn::Title => "title",
+            SortColumn::Tiles => "tiles",
+            SortColumn::Date => "date",
+            SortColumn::Size => "size",
+            SortColumn::Coverage => "coverage",
+        }
+    }
+}
+
+enum Outcome {
+    Confirm(Vec<usize>),
+    Cancel,
+}
+
+struct App<'a> {
+    datasets: &'a [DatasetMatch],
+    aoi: Option<(f64, f64, f64, f64)>,
+    /// Indices into `datasets` that pass the filter, in display order.
+    rows: Vec<usize>,
+    marked: BTreeSet<usize>,
+    state: TableState,
+    filter: String,
+    filtering: bool,
+    sort: SortColumn,
+    descending: bool,
+}
+
+impl<'a> App<'a> {
+    fn new(datasets: &'a [DatasetMatch], aoi: Option<(f64, f64, f64, f64)>) -> Self {
+        let mut app = App {
+            datasets,
+            aoi,
+            rows: vec![],
+            marked: BTreeSet::new(),
+            state: TableState::default(),
+            filter: String::new(),
+            filtering: false,
+            sort: SortColumn::Resolution,
+            descending: false,
+        };
+        app.refresh_rows();
+        app
+    }
+
+    fn coverage(&self, index: usize) -> Option<f64> {
+        self.aoi.map(|(lon_min, lat_min, lon_max, lat_max)| {
+            self.datasets[index].coverage(lon_min, lat_min, lon_max, lat_max)
+        })
+    }
+
+    fn compare(&self, a: usize, b: usize) -> Ordering {
+        let (a_set, b_set) = (&self.datasets[a], &self.datasets[b]);
+        let ordering = match self.sort {
+            // Datasets arrive sorted by resolution, then title.
+            SortColumn::Resolution => a.cmp(&b),
+            SortColumn::Title => a_set.title.cmp(&b_set.title),
+            SortColumn::Tiles => a_set.tiles.len().cmp(&b_set.tiles.len()),
+            SortColumn::Date => a_set.date_range().0.cmp(&b_set.date_range().0),
+            SortColumn::Size => a_set.size_estimate().cmp(&b_set.size_estimate()),
+            SortColumn::Coverage => self
+                .coverage(a)
+                .partial_cmp(&self.coverage(b))
+                .unwrap_or(Ordering::Equal),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Re-applies the search filter and sort order, keeping the highlighted dataset if it is still visible.
+    fn refresh_rows(&mut self) {
+        let current = self.current();
+        let filter = self.filter.to_lowercase();
+        let mut rows: Vec<usize> = (0..self.datasets.len())
+            .filter(|&index| {
+                let dataset = &self.datasets[index];
+                filter.is_empty()
+                    || dataset.title.to_lowercase().contains(&filter)
+                    || dataset.collection.id.to_lowercase().contains(&filter)
+            })
+            .collect();
+        rows.sort_by(|&a, &b| self.compare(a, b));
+        self.rows = rows;
+
+        let position = current
+            .and_then(|index| self.rows.iter().position(|&row| row == index))
+            .or(if self.rows.is_empty() { None } else { Some(0) });
+        self.state.select(position);
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.state
+            .selected()
+            .and_then(|position| self.rows.get(position).copied())
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        if self.rows.is_empty() {
+            return;
+        }
+        let position = self.state.selected().unwrap_or(0) as isize + delta;
+        let position = position.clamp(0, self.rows.len() as isize - 1) as usize;
+        self.state.select(Some(position));
+    }
+
+    fn toggle_current(&mut self) {
+        if let Some(index) = self.current() {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        if self.rows.iter().all(|index| self.marked.contains(index)) {
+            for index in &self.rows {
+                self.marked.remove(index);
+            }
+        } else {
+            self.marked.extend(self.rows.iter().copied());
+        }
+    }
+
+    /// Marked datasets, or the highlighted one if nothing is marked.
+    fn selection(&self) -> Vec<usize> {
+        if self.marked.is_empty() {
+            self.current().into_iter().collect()
+        } else {
+            self.marked.iter().copied().collect()
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Outcome> {
+        if self.filtering {
+            match key.code {
+                KeyCode::Enter | KeyCode::Esc => self.filtering = false,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.refresh_rows();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.refresh_rows();
+                }
+                _ => {}
+            }
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Some(Outcome::Cancel),
+            KeyCode::Enter => {
+                let selection = self.selection();
+                if !selection.is_empty() {
+                    return Some(Outcome::Confirm(selection));
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-10),
+            KeyCode::PageDown => self.move_by(10),
+            KeyCode::Home => self.move_by(isize::MIN / 2),
+            KeyCode::End => self.move_by(isize::MAX / 2),
+            KeyCode::Char(' ') => {
+                self.toggle_current();
+                self.move_by(1);
+            }
+            KeyCode::Char('a') => self.toggle_all(),
+            KeyCode::Char('/') => self.filtering = true,
+            KeyCode::Char('s') => {
+                self.sort = self.sort.next();
+                self.refresh_rows();
+            }
+            KeyCode::Char('r') => {
+                self.descending = !self.descending;
+                self.refresh_rows();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(5), Constraint::Length(1)]).areas(frame.area());
+        let [table_area, detail_area] =
+            Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)])
+                .areas(main);
+
+        let header = Row::new(["", "Title", "Tiles", "Res.", "Dates", "Size", "Coverage"])
+            .style(Style::new().bold());
+        let rows = self.rows.iter().map(|&index| {
+            let dataset = &self.datasets[index];
+            let mark = if self.marked.contains(&index) {
+                "[x]"
+            } else {
+                "[ ]"
+            };
+            Row::new([
+                Cell::from(mark),
+                Cell::from(dataset.title.clone()),
+                Cell::from(dataset.tiles.len().to_string()),
+                Cell::from(format_resolution(dataset)),
+                Cell::from(format_date_range(dataset)),
+                Cell::from(dataset.size_label()),
+                Cell::from(
+                    self.coverage(index)
+                        .map(|coverage| format!("{:.0}%", coverage * 100.0))
+                        .unwrap_or_else(|| "-".to_string()),
+                ),
+            ])
+        });
+        let title = format!(
+            " Datasets {}/{} | sort: {} {} | search: {}{} ",
+            self.rows.len(),
+            self.datasets.len(),
+            self.sort.label(),
+            if self.descending { "↓" } else { "↑" },
+            self.filter,
+            if self.filtering { "_" } else { "" }
+        );
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Fill(1),
+                Constraint::Length(7),
+                Constraint::Length(8),
+                Constraint::Length(23),
+                Constraint::Length(10),
+                Constraint::Length(8),
+            ],
+        )
+        .header(header)
+        .block(Block::bordered().title(title))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, table_area, &mut self.state);
+
+        let details = self
+            .current()
+            .map(|index| detail_lines(&self.datasets[index]))
+            .unwrap_or_default();
+        frame.render_widget(
+            Paragraph::new(details)
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(" Collection ")),
+            detail_area,
+        );
+        frame.render_widget(Paragraph::new(HELP).dim(), status);
+    }
+}
+
+fn format_resolution(dataset: &DatasetMatch) -> String {
+    let resolution = dataset.resolution();
+    if resolution == f64::MAX {
+        "-".to_string()
+    } else {
+        format!("{}m", resolution)
+    }
+}
+
+fn format_date_range(dataset: &DatasetMatch) -> String {
+    let format = |date: Option<chrono::DateTime<chrono::Utc>>| {
+        date.map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "..".to_string())
+    };
+    let (start, end) = dataset.date_range();
+    format!("{} - {}", format(start), format(end))
+}
+
+fn detail_lines(dataset: &DatasetMatch) -> Vec<Line<'static>> {
+    let collection = &dataset.collection;
+    let providers = collection
+        .providers
+        .iter()
+        .flatten()
+        .map(|provider| provider.name.clone())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut lines = vec![
+        Line::from(dataset.title.clone()).bold(),
+        Line::from(format!("ID: {}", collection.id)),
+        Line::from(format!("License: {}", collection.license)),
+        Line::from(format!("Providers: {}", providers)),
+        Line::from(format!("Dates: {}", format_date_range(dataset))),
+        Line::from(format!(
+            "Items: {}  Tiles: {}  Size: {}",
+            dataset.items.len(),
+            dataset.tiles.len(),
+            dataset.size_label()
+        )),
+    ];
+    for bbox in &collection.extent.spatial.bbox {
+        lines.push(Line::from(format!(
+            "Extent: {:.4}, {:.4}, {:.4}, {:.4}",
+            bbox.xmin(),
+            bbox.ymin(),
+            bbox.xmax(),
+            bbox.ymax()
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(collection.description.clone()));
+    lines
+}
+
+fn run(terminal: &mut DefaultTerminal, mut app: App) -> io::Result<Option<Vec<usize>>> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match app.handle_key(key) {
+                Some(Outcome::Confirm(selection)) => return Ok(Some(selection)),
+                Some(Outcome::Cancel) => return Ok(None),
+                None => {}
+            }
+        }
+    }
+}
+
+/// Lets the user pick one or more datasets from an interactive table.
+/// `aoi` is the searched area as (lon_min, lat_min, lon_max, lat_max), used for the coverage column.
+/// Returns the indices of the chosen datasets, or `None` if the user cancelled.
+pub fn select_datasets(
+    datasets: &[DatasetMatch],
+    aoi: Option<(f64, f64, f64, f64)>,
+) -> Result<Option<Vec<usize>>, MyError> {
+    // Undo raw mode if the terminal failed after it was switched on
+    let mut terminal = ratatui::try_init().map_err(|e| {
+        ratatui::restore();
+        MyError::TerminalError(e)
+    })?;
+    let result = run(&mut terminal, App::new(datasets, aoi));
+    ratatui::restore();
+    Ok(result?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::linz_s3_filter::dataset::Tile;
+    use ratatui::crossterm::event::KeyModifiers;
+    use stac::Collection;
+
+    fn dataset(title: &str, tiles: usize) -> DatasetMatch {
+        DatasetMatch {
+            title: title.to_string(),
+            collection: Collection::new(title.replace(' ', "-"), "a description"),
+            items: vec![],
+            tiles: (0..tiles)
+                .map(|i| Tile {
+                    href: format!("tile{}.tif", i),
+                    size: None,
+                    item_id: format!("item{}", i),
+                    item_href: None,
+                    checksum: None,
+                    updated: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Option<Outcome> {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn test_search_filters_rows() {
+        let datasets = vec![
+            dataset("Otago 1m DEM", 3),
+            dataset("Southland 1m DEM", 5),
+            dataset("Otago 8m DEM", 1),
+        ];
+        let mut app = App::new(&datasets, None);
+        press(&mut app, KeyCode::Char('/'));
+        for c in "otago".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        assert_eq!(app.rows, vec![0, 2]);
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Enter);
+        assert!(!app.filtering);
+        assert_eq!(app.filter, "otag");
+    }
+
+    #[test]
+    fn test_sort_by_tiles_and_reverse() {
+        let datasets = vec![dataset("a", 3), dataset("b", 5), dataset("c", 1)];
+        let mut app = App::new(&datasets, None);
+        press(&mut app, KeyCode::Char('s'));
+        press(&mut app, KeyCode::Char('s'));
+        assert_eq!(app.sort, SortColumn::Tiles);
+        assert_eq!(app.rows, vec![2, 0, 1]);
+        press(&mut app, KeyCode::Char('r'));
+        assert_eq!(app.rows, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_multi_select() {
+        let datasets = vec![dataset("a", 3), dataset("b", 5), dataset("c", 1)];
+        let mut app = App::new(&datasets, None);
+        press(&mut app, KeyCode::Char(' '));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Char(' '));
+        match press(&mut app, KeyCode::Enter) {
+            Some(Outcome::Confirm(selection)) => assert_eq!(selection, vec![0, 2]),
+            _ => panic!("expected a confirmed selection"),
+        }
+    }
+
+    #[test]
+    fn test_confirm_without_marks_picks_highlighted() {
+        let datasets = vec![dataset("a", 3), dataset("b", 5)];
+        let mut app = App::new(&datasets, None);
+        press(&mut app, KeyCode::Down);
+        match press(&mut app, KeyCode::Enter) {
+            Some(Outcome::Confirm(selection)) => assert_eq!(selection, vec![1]),
+            _ => panic!("expected a confirmed selection"),
+        }
+        assert!(matches!(
+            press(&mut app, KeyCode::Char('q')),
+            Some(Outcome::Cancel)
+        ));
+    }
+}
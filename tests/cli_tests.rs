@@ -12,7 +12,8 @@ fn test_latlonsearch() {
     let lat2 = "-45.2865";
     let lon2 = "175.7762";
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("coordinate")
         .arg(lat1)
@@ -30,7 +31,8 @@ fn test_latlonsearch() {
 #[serial]
 fn test_areasearch() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-first-index")
         .arg("area")
@@ -49,7 +51,8 @@ fn test_areasearch() {
 #[test]
 fn test_invalid_search_mode() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("imagery")
+    cmd.arg("search")
+        .arg("imagery")
         .arg("invalid_mode")
         .arg("40.9006")
         .arg("174.8860")
@@ -67,7 +70,11 @@ fn test_invalid_search_mode() {
 #[test]
 fn test_missing_arguments_for_areasearch() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation").arg("area").arg("-45.0").arg("167.0");
+    cmd.arg("search")
+        .arg("elevation")
+        .arg("area")
+        .arg("-45.0")
+        .arg("167.0");
     let num_lines = 0; // Specify the number of lines you want to match
     let pred = predicates::str::is_match(format!(r"^([^\n]*\n){{{}}}$", num_lines)).unwrap();
 
@@ -82,7 +89,10 @@ fn test_missing_arguments_for_areasearch() {
 #[test]
 fn test_missing_arguments_for_coordinatesearch() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation").arg("coordinate").arg("-45.0");
+    cmd.arg("search")
+        .arg("elevation")
+        .arg("coordinate")
+        .arg("-45.0");
     let num_lines = 0; // Specify the number of lines you want to match
     let pred = predicates::str::is_match(format!(r"^([^\n]*\n){{{}}}$", num_lines)).unwrap();
 
@@ -97,7 +107,8 @@ fn test_missing_arguments_for_coordinatesearch() {
 #[test]
 fn test_invalid_latlon_values() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("coordinate")
         .arg("invalid_lat")
@@ -115,7 +126,8 @@ fn test_invalid_latlon_values() {
 #[serial]
 fn test_empty_search_results() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("coordinate")
         .arg("-90.0")
@@ -136,7 +148,8 @@ fn test_empty_search_results() {
 #[serial]
 fn test_all_datasets() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-all")
         // make test more resilient by filtering by name
@@ -152,10 +165,31 @@ fn test_all_datasets() {
     cmd.assert().success().stdout(pred);
 }
 
+#[test]
+#[serial]
+fn test_index_range_selection() {
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("search")
+        .arg("elevation")
+        .arg("--disable-download")
+        .arg("--by-index")
+        .arg("0-1")
+        .arg("--include-collection-name")
+        .arg("New Zealand DEM Hillshade")
+        .arg("coordinate")
+        .arg("-45.9006")
+        .arg("170.8860");
+
+    let num_lines = 2; // Same tiles as selecting both datasets with --by-all
+    let pred = predicates::str::is_match(format!(r"^([^\n]*\n){{{}}}$", num_lines)).unwrap();
+    cmd.assert().success().stdout(pred);
+}
+
 #[test]
 fn test_invalid_args() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-first-index")
         .arg("-s")
@@ -180,7 +214,8 @@ fn test_valid_search_with_download() {
     let temp_path = temp_dir.path();
 
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("coordinate")
         .arg("-45.9006")
         .arg("170.8860")
@@ -210,7 +245,8 @@ fn test_valid_search_with_download() {
 #[serial]
 fn test_valid_search_with_condition() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-first-index")
         .arg("coordinate")
@@ -228,7 +264,8 @@ fn test_valid_search_with_condition() {
 fn test_valid_search_with_index() {
     // could improve check
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--include-collection-name")
         .arg("Southland LiDAR 1m")
@@ -248,7 +285,8 @@ fn test_valid_search_with_index() {
 #[serial]
 fn test_valid_search_with_missing_index() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-index") // No specific index provided, should default to 0
         .arg("45.5")
@@ -266,7 +304,8 @@ fn test_valid_search_with_missing_index() {
 #[serial]
 fn test_invalid_search_with_out_of_bounds_index() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--by-index")
         .arg(usize::MAX.to_string()) // Specify an out-of-bounds index
@@ -277,13 +316,14 @@ fn test_invalid_search_with_out_of_bounds_index() {
         .arg("175.7762");
     let pred = predicates::str::contains("is out of bounds. There are only"); // Adjust the expected error message
 
-    cmd.assert().stderr(pred).success();
+    cmd.assert().stderr(pred).code(2);
 }
 #[test]
 #[serial]
 fn test_valid_search_with_conditon_and_one_result() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--include-collection-name")
         .arg("Southland LiDAR 1m DEM")
@@ -301,7 +341,8 @@ fn test_valid_search_with_conditon_and_one_result() {
 #[serial]
 fn test_valid_search_with_conditon_and_mulitple_result() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--include-collection-name")
         .arg("Southland")
@@ -321,7 +362,8 @@ fn test_valid_search_with_conditon_and_mulitple_result() {
 #[serial]
 fn test_valid_search_with_multiple_filters() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--include-collection-name")
         .arg("Southland LiDAR 1m DEM (2020-2024)")
@@ -344,7 +386,8 @@ fn test_valid_search_with_multiple_filters() {
 #[serial]
 fn test_valid_search_with_exclusion_filters() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--exclude-collection-name")
         .arg("Hillshade")
@@ -362,7 +405,8 @@ fn test_valid_search_with_exclusion_filters() {
 #[serial]
 fn test_valid_search_with_exclusion_inclusion_filters() {
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--disable-download")
         .arg("--include-collection-name")
         .arg("Southland LiDAR 1m DEM (2020-2024)")
@@ -390,7 +434,8 @@ fn test_valid_search_with_download_and_cache() {
     let cache_dir = tempdir().unwrap();
     let cache_path = cache_dir.path();
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--cache")
         .arg(cache_path)
         .arg("--include-collection-name")
@@ -414,7 +459,8 @@ fn test_valid_search_with_download_and_cache() {
     let file_number = 2;
     check_folder_content(&files, file_number);
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--include-collection-name")
         .arg("Southland")
         .arg("coordinate")
@@ -443,7 +489,8 @@ fn test_valid_search_with_download_and_cache() {
         .collect();
     // Run the command again
     let mut cmd = Command::cargo_bin("linz_s3").unwrap();
-    cmd.arg("elevation")
+    cmd.arg("search")
+        .arg("elevation")
         .arg("--include-collection-name")
         .arg("Southland")
         .arg("coordinate")
@@ -483,6 +530,131 @@ fn test_valid_search_with_download_and_cache() {
     assert_eq!(mod_times_before, mod_times_after, "Files were overwritten",);
 }
 
+#[test]
+#[serial]
+fn test_list_collections() {
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("list")
+        .arg("elevation")
+        .arg("--include-collection-name")
+        .arg("New Zealand DEM Hillshade");
+    // One line per collection, without a spatial filter and without fetching items.
+    let pred =
+        predicates::str::is_match(r"^([^\n]*\tNew Zealand DEM Hillshade[^\n]*\t\d+ items\n)+$")
+            .unwrap();
+    cmd.assert().success().stdout(pred);
+}
+
+#[test]
+fn test_config_show_and_init() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config").arg("show").current_dir(temp_dir.path());
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("region = \"ap-southeast-2\""));
+
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config").arg("init").current_dir(temp_dir.path());
+    cmd.assert().success();
+    assert!(temp_dir.path().join("config.toml").is_file());
+
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config").arg("init").current_dir(temp_dir.path());
+    cmd.assert()
+        .stderr(predicates::str::contains("already exists"))
+        .code(2);
+}
+
+#[test]
+fn test_config_with_broken_file() {
+    let temp_dir = tempdir().unwrap();
+    std::fs::write(temp_dir.path().join("config.toml"), "region = [").unwrap();
+
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config").arg("show").current_dir(temp_dir.path());
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::contains("region = \"ap-southeast-2\""))
+        .stderr(predicates::str::contains("could not be read"));
+
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config")
+        .arg("init")
+        .arg("--force")
+        .current_dir(temp_dir.path());
+    cmd.assert().success();
+    let config = std::fs::read_to_string(temp_dir.path().join("config.toml")).unwrap();
+    assert!(config.contains("region = \"ap-southeast-2\""));
+
+    // Other commands still refuse a broken config
+    std::fs::write(temp_dir.path().join("config.toml"), "region = [").unwrap();
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("cache")
+        .arg("list")
+        .arg(temp_dir.path())
+        .current_dir(temp_dir.path());
+    cmd.assert().code(3);
+}
+
+#[test]
+fn test_progress_file() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config")
+        .arg("show")
+        .arg("--progress")
+        .arg("json")
+        .arg("--progress-file")
+        .arg("events.jsonl")
+        .current_dir(temp_dir.path());
+    cmd.assert().success();
+    assert!(temp_dir.path().join("events.jsonl").is_file());
+
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config").arg("show").arg("--progress").arg("xml");
+    cmd.assert().code(2);
+}
+
+#[test]
+fn test_progress_json_on_stderr() {
+    let temp_dir = tempdir().unwrap();
+    // A broken config makes config show log a warning
+    fs::write(temp_dir.path().join("config.toml"), "region = 3").unwrap();
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("config")
+        .arg("show")
+        .arg("--progress")
+        .arg("json")
+        .current_dir(temp_dir.path());
+    let output = cmd.assert().success().get_output().clone();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let events: Vec<serde_json::Value> = stderr
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert!(events
+        .iter()
+        .any(|event| event["event"] == "log" && event["level"] == "WARN"));
+}
+
+#[test]
+fn test_cache_list_empty() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("cache").arg("list").arg(temp_dir.path());
+    cmd.assert().success().stdout("");
+}
+
+#[test]
+fn test_missing_subcommand() {
+    let mut cmd = Command::cargo_bin("linz_s3").unwrap();
+    cmd.arg("elevation");
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("unrecognized subcommand"));
+}
+
 fn check_folder_content(files: &[PathBuf], file_number: usize) {
     // Check if there is exactly one subfolder in the temporary directory
     let subfolders: Vec<_> = files.iter().filter(|path| path.is_dir()).collect();
@@ -495,10 +667,13 @@ fn check_folder_content(files: &[PathBuf], file_number: usize) {
     // Get the path of the subfolder
     let subfolder_path = subfolders[0];
 
-    // Check the contents of the subfolder
+    // Check the contents of the subfolder, next to the download manifests
+    assert!(subfolder_path.join("manifest.json").is_file());
+    assert!(subfolder_path.join("manifest.csv").is_file());
     let subfolder_files: Vec<_> = fs::read_dir(subfolder_path)
         .unwrap()
         .map(|entry| entry.unwrap().path())
+        .filter(|path| !path.ends_with("manifest.json") && !path.ends_with("manifest.csv"))
         .collect();
     assert_eq!(
         subfolder_files.len(),
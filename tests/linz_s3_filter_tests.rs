@@ -1,6 +1,10 @@
-use linz_s3::linz_s3_filter::{dataset, linz_bucket::LinzBucket, utils};
+use linz_s3::linz_s3_filter::{
+    dataset,
+    linz_bucket::{LinzBucket, TileQuery},
+    utils,
+};
 use serial_test::serial;
-use std::sync::Once;
+use std::sync::{Arc, Once};
 
 static INIT: Once = Once::new();
 
@@ -9,6 +13,13 @@ fn init_logger() {
         env_logger::builder().is_test(true).init();
     });
 }
+
+fn within(lon_min: f64, lat_min: f64, lon_max: f64, lat_max: f64) -> TileQuery {
+    TileQuery {
+        bounds: Some((lon_min, lat_min, lon_max, lat_max)),
+        ..Default::default()
+    }
+}
 #[tokio::test]
 #[serial]
 async fn test_get_tiles_from_lat_lon_empty() {
@@ -20,7 +31,7 @@ async fn test_get_tiles_from_lat_lon_empty() {
     let lon = 174.8860;
     let tiles = linz_bucket
         .unwrap()
-        .get_tiles(Some(lat), Some(lon), None, None)
+        .get_tiles(&within(lon, lat, lon, lat))
         .await;
     assert!(tiles.is_empty());
 }
@@ -35,7 +46,7 @@ async fn test_get_tiles_from_lat_lon() {
     let lon = 167.0;
     let tiles = linz_bucket
         .unwrap()
-        .get_tiles(Some(lat), Some(lon), None, None)
+        .get_tiles(&within(lon, lat, lon, lat))
         .await;
     assert!(!tiles.is_empty());
 }
@@ -53,7 +64,7 @@ async fn test_get_tiles_from_lat_lon_range() {
     let lon2 = 175.7762;
     let tiles = linz_bucket
         .unwrap()
-        .get_tiles(Some(lat1), Some(lon1), Some(lat2), Some(lon2))
+        .get_tiles(&within(lon1, lat1, lon2, lat2))
         .await;
     assert!(!tiles.is_empty());
 }
@@ -73,7 +84,7 @@ async fn test_get_tiles_from_point_and_dimension() {
         utils::get_coordinate_from_dimension(lat, lon, width_m, height_m);
     let tiles = linz_bucket
         .unwrap()
-        .get_tiles(Some(lat1), Some(lon1), Some(lat2), Some(lon2))
+        .get_tiles(&within(lon1, lat1, lon2, lat2))
         .await;
     assert!(!tiles.is_empty());
 }
@@ -83,18 +94,74 @@ async fn test_get_tiles_from_point_and_dimension_filter() {
     init_logger();
 
     let dataset = dataset::BucketName::Elevation;
-    let mut linz_bucket = LinzBucket::initialise_catalog(dataset, Some(1))
+    let linz_bucket = LinzBucket::initialise_catalog(dataset, Some(1))
         .await
         .unwrap();
-    linz_bucket.set_collection_filter(Some(&["Southland".to_string()]), None, None);
     let lat = -45.0;
     let lon = 167.0;
     let width_m = 100000.0; // 100 km
     let height_m = 100000.0; // 100 km
     let (lat1, lon1, lat2, lon2) =
         utils::get_coordinate_from_dimension(lat, lon, width_m, height_m);
-    let tiles = linz_bucket
-        .get_tiles(Some(lat1), Some(lon1), Some(lat2), Some(lon2))
-        .await;
+    let query = TileQuery {
+        include: vec!["Southland".to_string()],
+        ..within(lon1, lat1, lon2, lat2)
+    };
+    let tiles = linz_bucket.get_tiles(&query).await;
     assert!(!tiles.is_empty());
+    assert!(tiles
+        .iter()
+        .all(|dataset| dataset.title.contains("Southland")));
+}
+
+#[tokio::test]
+#[serial]
+async fn test_concurrent_searches() {
+    init_logger();
+
+    let dataset = dataset::BucketName::Elevation;
+    let linz_bucket = Arc::new(
+        LinzBucket::initialise_catalog(dataset, Some(1))
+            .await
+            .unwrap(),
+    );
+    let southland = TileQuery {
+        include: vec!["Southland".to_string()],
+        ..within(167.0, -45.0, 167.0, -45.0)
+    };
+    let empty = within(174.8860, 40.9006, 174.8860, 40.9006);
+    let cancelled = TileQuery {
+        reporter: Default::default(),
+        cancel: Default::default(),
+        ..southland.clone()
+    };
+    cancelled.cancel.cancel();
+    let (southland_progress, empty_progress) = (southland.progress(), empty.progress());
+
+    let (southland_tiles, empty_tiles, cancelled_tiles) = tokio::join!(
+        tokio::spawn({
+            let linz_bucket = linz_bucket.clone();
+            async move { linz_bucket.get_tiles(&southland).await }
+        }),
+        tokio::spawn({
+            let linz_bucket = linz_bucket.clone();
+            async move { linz_bucket.get_tiles(&empty).await }
+        }),
+        tokio::spawn({
+            let linz_bucket = linz_bucket.clone();
+            async move { linz_bucket.get_tiles(&cancelled).await }
+        }),
+    );
+    assert!(!southland_tiles.unwrap().is_empty());
+    assert!(empty_tiles.unwrap().is_empty());
+    // Cancelling one query leaves the others running
+    assert!(cancelled_tiles.unwrap().is_empty());
+    // Each query reports its own progress
+    let southland_progress = *southland_progress.borrow();
+    assert!(southland_progress.collections_total > 0);
+    assert_eq!(
+        southland_progress.collections_read,
+        southland_progress.collections_total
+    );
+    assert_eq!(empty_progress.borrow().collections_total, 0);
 }